@@ -0,0 +1,79 @@
+use {
+    crate::*,
+    redis::{streams::StreamMaxlen, Commands},
+    serde::Deserialize,
+};
+
+/// Configuration of the audit trail, a Redis stream recording
+/// the tasks produced from the input tasks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConf {
+
+    /// the stream, on the Redis server of each watcher
+    pub stream: String,
+
+    /// the approximate number of entries kept in the stream
+    #[serde(default = "AuditConf::default_max_len")]
+    pub max_len: usize,
+
+}
+
+/// What was done with a produced task
+#[derive(Debug, Clone, Copy)]
+pub enum AuditOutcome {
+    Pushed,
+    Delayed,
+    /// the task was a duplicate, already in the task set or dedup key
+    AlreadyQueued,
+}
+
+/// The record of a task produced by a rule
+pub struct AuditEntry<'a> {
+    pub time: f64,
+    /// the input of the watcher
+    pub watcher: &'a str,
+    /// the input task
+    pub input: &'a str,
+    pub rule: &'a str,
+    /// the produced task
+    pub task: &'a str,
+    pub target: &'a TaskTarget,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditConf {
+    pub fn default_max_len() -> usize {
+        10_000
+    }
+
+    /// add an entry to the stream, trimming it
+    pub fn record(
+        &self,
+        con: &mut RedisConnection,
+        entry: &AuditEntry<'_>,
+    ) -> Result<(), RescError> {
+        let time = entry.time.to_string();
+        let fields = [
+            ("time", time.as_str()),
+            ("watcher", entry.watcher),
+            ("input", entry.input),
+            ("rule", entry.rule),
+            ("task", entry.task),
+            ("target", entry.target.name()),
+            ("outcome", entry.outcome.as_str()),
+        ];
+        let _: () = con.xadd_maxlen(&self.stream, StreamMaxlen::Approx(self.max_len), "*", &fields)?;
+        Ok(())
+    }
+}
+
+impl AuditOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pushed => "pushed",
+            Self::Delayed => "delayed",
+            Self::AlreadyQueued => "already_queued",
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use {
+    std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// An exponential backoff with jitter, for the computation
+/// of the delays between successive attempts
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { next: initial, max }
+    }
+    /// return the delay to wait before the next attempt,
+    /// taken randomly between half and all of the current
+    /// base delay, and double the base delay
+    pub fn next_delay(&mut self) -> Duration {
+        let jitter = 0.5 + 0.5 * random_ratio();
+        let delay = self.next.mul_f64(jitter);
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+}
+
+/// a pseudo-random number in [0, 1]
+fn random_ratio() -> f64 {
+    let n = RandomState::new().build_hasher().finish();
+    n as f64 / u64::MAX as f64
+}
+
+/// sleep for the given duration, or less if `stop` is set meanwhile
+pub fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let end = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= end {
+            return;
+        }
+        thread::sleep((end - now).min(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,39 @@
+use {
+    chrono::{SecondsFormat, Utc},
+    lazy_static::lazy_static,
+    std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// the variables resc values itself, usable in make patterns
+pub const BUILTIN_VARIABLES: &[&str] = &["now", "now_ts", "now_iso", "uuid", "hostname", "seq"];
+
+/// the number of tasks made since resc started
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref HOSTNAME: String = hostname::get()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+}
+
+/// a copy of the properties, with the built-in variables
+/// added unless they're already there
+pub fn with_builtins(props: &HashMap<String, String>) -> HashMap<String, String> {
+    let now = Utc::now();
+    let builtins = [
+        ("now", now.timestamp().to_string()),
+        ("now_ts", now.timestamp().to_string()),
+        ("now_iso", now.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ("uuid", uuid::Uuid::new_v4().to_string()),
+        ("hostname", HOSTNAME.clone()),
+        ("seq", (SEQ.fetch_add(1, Ordering::Relaxed) + 1).to_string()),
+    ];
+    let mut props = props.clone();
+    for (name, value) in builtins {
+        props.entry(name.to_string()).or_insert(value);
+    }
+    props
+}
@@ -0,0 +1,145 @@
+use {
+    crate::*,
+    std::collections::HashSet,
+};
+
+/// Check the whole configuration, beyond what's checked on
+/// deserialization, and return the problems found, each one
+/// prefixed with its path in the configuration
+pub fn check_conf(conf: &Conf) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (wi, watcher) in conf.watchers.iter().enumerate() {
+        if let Err(e) = watcher.check_input() {
+            problems.push(format!("watchers[{}]: {}", wi, e));
+        }
+        let reads_queues = watcher.input_queue.is_some() || watcher.input_queue_pattern.is_some();
+        if watcher.stale_taken_age.is_some() && !reads_queues {
+            problems.push(format!("watchers[{}].stale_taken_age: only queue watchers have a taken queue", wi));
+        }
+        if watcher.taken_timeout.is_some() && !reads_queues {
+            problems.push(format!("watchers[{}].taken_timeout: only queue watchers have a taken queue", wi));
+        }
+        if watcher.discovery_interval.is_some() && watcher.input_queue_pattern.is_none() {
+            problems.push(format!("watchers[{}].discovery_interval: input_queue_pattern is needed", wi));
+        }
+        if watcher.max_tasks_per_second.is_some_and(|max| max <= 0.0 || !max.is_finite()) {
+            problems.push(format!("watchers[{}].max_tasks_per_second: must be positive", wi));
+        }
+        if watcher.stale_taken_check_interval.is_some() && watcher.stale_taken_age.is_none() {
+            problems.push(format!("watchers[{}].stale_taken_check_interval: stale_taken_age is needed", wi));
+        }
+        for (ri, rule) in watcher.rules.iter().enumerate() {
+            let path = format!("watchers[{}].rules[{}] ({:?})", wi, ri, rule.name);
+            check_rule(rule, &path, &mut problems);
+            let requeuable = reads_queues || watcher.input_stream.is_some();
+            if rule.on_failure == FailurePolicy::Requeue && !requeuable {
+                problems.push(format!("{}.on_failure: messages of channels can't be requeued", path));
+            }
+            for (mi, maker) in rule.makers.as_slice().iter().enumerate() {
+                if let Some(name) = &maker.redis {
+                    if !conf.redis_servers.contains_key(name) {
+                        problems.push(format!("{}.make[{}].redis: unknown Redis server {:?}", path, mi, name));
+                    }
+                }
+            }
+        }
+    }
+    if conf.tracing.is_some() && !cfg!(feature = "otlp") {
+        problems.push("tracing: resc was compiled without the otlp feature".to_string());
+    }
+    problems
+}
+
+/// Check all variables used in the rule's patterns can be valued
+fn check_rule(rule: &Rule, path: &str, problems: &mut Vec<String>) {
+    let mut known: HashSet<&str> = rule.on_regex.capture_names().collect();
+    known.insert("input_task");
+    if rule.input_queue.is_some() {
+        known.insert("input_queue");
+    }
+    if let Some(transform) = &rule.transform {
+        known.extend(transform.assigned_properties());
+    }
+    let check_variables = |
+        problems: &mut Vec<String>,
+        variables: Vec<&str>,
+        var_path: String,
+        namespaces: &[&str],
+        builtins: bool,
+    | {
+        for var in variables {
+            let in_namespace = namespaces.iter()
+                .any(|ns| var.strip_prefix(ns).is_some_and(|rest| rest.starts_with('.')));
+            let builtin = builtins && BUILTIN_VARIABLES.contains(&var);
+            if !known.contains(var) && !in_namespace && !builtin {
+                problems.push(format!("{}.{}: unknown variable {:?}", path, var_path, var));
+            }
+        }
+    };
+    let check_pattern = |
+        problems: &mut Vec<String>,
+        pattern: &Pattern,
+        pattern_path: String,
+        namespaces: &[&str],
+        builtins: bool,
+    | {
+        check_variables(problems, pattern.variables().collect(), pattern_path, namespaces, builtins);
+    };
+    let mut namespaces: Vec<&str> = Vec::new();
+    if rule.task_format == TaskFormat::Json {
+        namespaces.push("payload");
+    }
+    if let Some(debounce_key) = &rule.debounce_key {
+        if rule.debounce_ms.is_none() {
+            problems.push(format!("{}.debounce_key: debounce_ms is needed", path));
+        }
+        check_pattern(problems, debounce_key, "debounce_key".to_string(), &namespaces, false);
+    }
+    for (fi, fetcher) in rule.fetchers.iter().enumerate() {
+        for (pattern_path, pattern) in fetcher.patterns() {
+            check_pattern(problems, &pattern, format!("fetch[{}].{}", fi, pattern_path), &namespaces, false);
+        }
+    }
+    namespaces.extend(rule.fetchers.iter().map(|f| f.returns()));
+    for (mi, maker) in rule.makers.as_slice().iter().enumerate() {
+        if let Some(when) = &maker.when {
+            check_variables(problems, when.variables(), format!("make[{}].when", mi), &namespaces, false);
+        }
+        for pattern in maker.task.patterns() {
+            check_pattern(problems, &pattern, format!("make[{}].task", mi), &namespaces, true);
+        }
+        if let Some(queue) = &maker.queue {
+            check_pattern(problems, queue, format!("make[{}].queue", mi), &namespaces, true);
+        }
+        if let Some(stream) = &maker.stream {
+            check_pattern(problems, stream, format!("make[{}].stream", mi), &namespaces, true);
+        }
+        if let Some(zset) = &maker.zset {
+            check_pattern(problems, zset, format!("make[{}].zset", mi), &namespaces, true);
+        }
+        if let Some(score) = &maker.score {
+            check_pattern(problems, score, format!("make[{}].score", mi), &namespaces, true);
+        }
+        if let Some(set) = &maker.set {
+            check_pattern(problems, set, format!("make[{}].set", mi), &namespaces, true);
+        }
+        if let Some(dedup_key) = &maker.dedup_key {
+            check_pattern(problems, dedup_key, format!("make[{}].dedup_key", mi), &namespaces, true);
+        }
+        if let Some(delay) = &maker.delay {
+            check_pattern(problems, delay, format!("make[{}].delay", mi), &namespaces, true);
+        }
+        if let Some(join) = &maker.join {
+            check_pattern(problems, &join.key, format!("make[{}].join.key", mi), &namespaces, true);
+            check_pattern(problems, &join.count, format!("make[{}].join.count", mi), &namespaces, true);
+        }
+        for (ei, effect) in maker.effects.iter().enumerate() {
+            for (name, pattern) in effect.patterns() {
+                check_pattern(problems, pattern, format!("make[{}].effects[{}].{}", mi, ei, name), &namespaces, true);
+            }
+        }
+    }
+    if let Some(filter) = &rule.filter {
+        check_variables(problems, filter.variables(), "filter".to_string(), &namespaces, false);
+    }
+}
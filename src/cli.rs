@@ -0,0 +1,251 @@
+use {
+    regex::Regex,
+    std::env,
+};
+
+/// What resc was asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// run the watchers (the default)
+    Run,
+    /// check the configuration and exit
+    Check,
+    /// run the tests of the rules, and exit
+    Test,
+    /// tell what the configuration does with a task, and exit
+    Explain,
+    /// tell what the configuration does with the tasks of a file, and exit
+    Simulate,
+    /// push a task in the input queue of a watcher, and exit
+    Inject,
+    /// print the lengths of the queues, and exit
+    Queues,
+    /// move tasks back to an input queue, and exit
+    Replay,
+}
+
+/// The parsed launch arguments
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub conf_path: String,
+    /// whether the tasks must be evaluated without
+    /// anything being written
+    pub dry_run: bool,
+    /// whether the problems found in the configuration
+    /// prevent the scheduler from starting
+    pub strict: bool,
+    /// the path of the file whose responses replace the
+    /// queries of the HTTP fetchers
+    pub mocks_path: Option<String>,
+    /// the task to explain or inject
+    pub task: Option<String>,
+    /// the queue where to inject or replay the tasks, when
+    /// it can't be deduced from the configuration
+    pub queue: Option<String>,
+    /// the queue whose tasks are replayed
+    pub from: Option<String>,
+    /// the maximal number of tasks to replay
+    pub limit: Option<usize>,
+    /// the regex the replayed tasks must match
+    pub filter: Option<Regex>,
+    /// the maximal number of tasks replayed per second
+    pub rate: Option<f64>,
+    /// the path of the JSON file giving the results of the
+    /// fetchers, when explaining a task
+    pub fetch_results_path: Option<String>,
+    /// the path of the file of the tasks to simulate, one per line
+    pub tasks_path: Option<String>,
+    /// the path of the JSON report of the simulation, if any
+    pub report_path: Option<String>,
+}
+
+pub const USAGE: &str = "\
+Usage:
+    resc [<options>] <conf-file>  run the scheduler
+    resc check <conf-file>        check the configuration
+    resc test [<options>] <conf-file>
+                                  run the tests of the rules
+    resc explain [<options>] <conf-file> <task> [<fetch-results-file>]
+                                  tell what the configuration does with a task
+    resc simulate --tasks <tasks-file> [<options>] <conf-file>
+                                  tell what the configuration does with the tasks
+                                  of a file, one per line
+    resc inject [--queue <queue>] <conf-file> <task>
+                                  push a task in the input queue of a watcher
+    resc queues <conf-file>       print the lengths of the queues of the configuration
+    resc replay --from <queue> [<options>] <conf-file>
+                                  move the tasks of a dead letter or unmatched queue
+                                  back to the input queue
+Options:
+    --dry-run                     evaluate the incoming tasks without writing anything
+                                  (only when running the scheduler)
+    --strict                      don't start the scheduler when the configuration
+                                  has problems, as found by resc check
+    --mock-fetchers <mock-file>   answer the HTTP fetchers with the responses of the
+                                  file instead of querying
+    --report <report-file>        write the result of the simulation as JSON
+    --queue <queue>               the input queue where to inject or replay the tasks
+    --limit <n>                   replay at most n tasks
+    --filter <regex>              replay only the tasks matching the regex
+    --rate <n>                    replay at most n tasks per second (default: 10)
+Options may be given before or after the other arguments, -- ending them.
+";
+
+impl Args {
+    pub fn from_env() -> Result<Self, String> {
+        let mut args = env::args().skip(1).peekable();
+        let command = match args.peek().map(|s| s.as_str()) {
+            Some("check") => {
+                args.next();
+                Command::Check
+            }
+            Some("test") => {
+                args.next();
+                Command::Test
+            }
+            Some("explain") => {
+                args.next();
+                Command::Explain
+            }
+            Some("simulate") => {
+                args.next();
+                Command::Simulate
+            }
+            Some("inject") => {
+                args.next();
+                Command::Inject
+            }
+            Some("queues") => {
+                args.next();
+                Command::Queues
+            }
+            Some("replay") => {
+                args.next();
+                Command::Replay
+            }
+            _ => Command::Run,
+        };
+        let mut dry_run = false;
+        let mut strict = false;
+        let mut positionals = Vec::new();
+        let mut mocks_path = None;
+        let mut tasks_path = None;
+        let mut report_path = None;
+        let mut queue = None;
+        let mut from = None;
+        let mut limit = None;
+        let mut filter = None;
+        let mut rate = None;
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positionals.push(arg);
+                continue;
+            }
+            let option = arg;
+            match option.as_str() {
+                "--" => {
+                    positionals.extend(args.by_ref());
+                }
+                "--dry-run" => {
+                    if command != Command::Run {
+                        return Err("--dry-run is only for running the scheduler".to_string());
+                    }
+                    dry_run = true;
+                }
+                "--strict" => {
+                    if command != Command::Run {
+                        return Err("--strict is only for running the scheduler".to_string());
+                    }
+                    strict = true;
+                }
+                "--mock-fetchers" => {
+                    if matches!(command, Command::Check | Command::Inject | Command::Queues | Command::Replay) {
+                        return Err("--mock-fetchers is only for commands applying the rules".to_string());
+                    }
+                    mocks_path = Some(args.next().ok_or_else(|| "no mock file provided".to_string())?);
+                }
+                "--queue" => {
+                    if !matches!(command, Command::Inject | Command::Replay) {
+                        return Err("--queue is only for injecting or replaying tasks".to_string());
+                    }
+                    queue = Some(args.next().ok_or_else(|| "no queue provided".to_string())?);
+                }
+                "--from" | "--limit" | "--filter" | "--rate" => {
+                    if command != Command::Replay {
+                        return Err(format!("{} is only for replaying tasks", option));
+                    }
+                    let value = args.next().ok_or_else(|| format!("no value provided for {}", option))?;
+                    match option.as_str() {
+                        "--from" => {
+                            from = Some(value);
+                        }
+                        "--limit" => {
+                            limit = Some(value.parse().map_err(|_| format!("invalid limit: {:?}", value))?);
+                        }
+                        "--filter" => {
+                            filter = Some(Regex::new(&value).map_err(|e| format!("invalid filter: {}", e))?);
+                        }
+                        _ => {
+                            rate = Some(value.parse::<f64>()
+                                .ok()
+                                .filter(|rate| *rate > 0.0)
+                                .ok_or_else(|| format!("invalid rate: {:?}", value))?);
+                        }
+                    }
+                }
+                "--tasks" | "--report" => {
+                    if command != Command::Simulate {
+                        return Err(format!("{} is only for simulations", option));
+                    }
+                    let path = args.next().ok_or_else(|| format!("no file provided for {}", option))?;
+                    if option == "--tasks" {
+                        tasks_path = Some(path);
+                    } else {
+                        report_path = Some(path);
+                    }
+                }
+                _ => {
+                    return Err(format!("unknown option: {:?}", option));
+                }
+            }
+        }
+        let mut args = positionals.into_iter();
+        let conf_path = args.next()
+            .ok_or_else(|| "no configuration file provided".to_string())?;
+        let mut task = None;
+        let mut fetch_results_path = None;
+        if command == Command::Explain {
+            task = Some(args.next().ok_or_else(|| "no task to explain provided".to_string())?);
+            fetch_results_path = args.next();
+        }
+        if command == Command::Inject {
+            task = Some(args.next().ok_or_else(|| "no task to inject provided".to_string())?);
+        }
+        if command == Command::Replay && from.is_none() {
+            return Err("no queue to replay provided, use --from".to_string());
+        }
+        if command == Command::Simulate && tasks_path.is_none() {
+            return Err("no tasks file provided, use --tasks".to_string());
+        }
+        if let Some(arg) = args.next() {
+            return Err(format!("unexpected argument: {:?}", arg));
+        }
+        Ok(Self {
+            command,
+            conf_path,
+            dry_run,
+            strict,
+            mocks_path,
+            task,
+            queue,
+            from,
+            limit,
+            filter,
+            rate,
+            fetch_results_path,
+            tasks_path,
+            report_path,
+        })
+    }
+}
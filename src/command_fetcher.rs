@@ -0,0 +1,111 @@
+use {
+    crate::*,
+    log::*,
+    serde::Deserialize,
+    serde_json::Value,
+    std::{
+        collections::HashMap,
+        io::Read,
+        process::{Command, Stdio},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// A fetcher running an external command, whose standard
+/// output is made of JSON lines, each one giving a result
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandFetcher {
+    pub returns: String,
+
+    /// the program to run
+    pub command: String,
+
+    /// the arguments of the program
+    #[serde(default)]
+    pub args: Vec<Pattern>,
+
+    /// the environment variables given to the program,
+    /// in addition to the ones of resc
+    #[serde(default)]
+    pub env: HashMap<String, Pattern>,
+
+    /// the duration after which the program is killed
+    /// and the fetch fails, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+impl CommandFetcher {
+    pub fn results(
+        &self,
+        props: &HashMap<String, String>,
+    ) -> Result<Vec<FetchResult>, FetchError> {
+        let args: Vec<String> = self.args.iter()
+            .map(|arg| arg.inject(props))
+            .collect();
+        info!("  running command {:?} {:?}", &self.command, &args);
+        let mut child = Command::new(&self.command)
+            .args(&args)
+            .envs(self.env.iter().map(|(name, value)| (name, value.inject(props))))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // the output is read in another thread so that a full
+        // pipe doesn't prevent the program from ending
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let timeout = self.timeout_ms.map(Duration::from_millis);
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+                child.kill()?;
+                child.wait()?;
+                return Err(FetchError::Command(format!("{:?} timed out", &self.command)));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let output = reader.join()
+            .map_err(|_| FetchError::Command("output can't be read".to_string()))??;
+        if !status.success() {
+            return Err(FetchError::Command(format!("{:?} failed: {}", &self.command, status)));
+        }
+        let mut results = Vec::new();
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line)? {
+                Value::Object(object) => {
+                    results.push(FetchResult::from_object(&self.returns, &object));
+                }
+                _ => {
+                    return Err(FetchError::UnexpectedContent);
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl Fetch for CommandFetcher {
+    fn returns(&self) -> &str {
+        &self.returns
+    }
+    fn patterns(&self) -> Vec<(String, Pattern)> {
+        let mut patterns = Vec::new();
+        for (i, arg) in self.args.iter().enumerate() {
+            patterns.push((format!("args[{}]", i), arg.clone()));
+        }
+        for (name, value) in &self.env {
+            patterns.push((format!("env.{}", name), value.clone()));
+        }
+        patterns
+    }
+    fn results(&self, props: &HashMap<String, String>) -> RescResult<Vec<FetchResult>> {
+        Ok(CommandFetcher::results(self, props)?)
+    }
+}
@@ -0,0 +1,431 @@
+use {
+    crate::*,
+    regex::Regex,
+    serde::{de, Deserialize, Deserializer},
+    std::{cmp::Ordering, collections::HashMap, fmt},
+};
+
+/// A boolean expression on the properties, like
+/// `member.status == "active" && member.age >= 18`.
+///
+/// Operands are variables (e.g. `member.status` or `${member.status}`), whose names
+/// are made of letters, digits, `_`, and `.`, string literals in double or single
+/// quotes, and numbers. Operators are `==`, `!=`,
+/// `<`, `<=`, `>`, `>=`, `=~` (regex match, the regex being a literal),
+/// `&&`, `||`, and `!`, and parentheses can be used.
+///
+/// Two values which both look like numbers are compared as numbers,
+/// other ones as strings. A missing variable is an empty string, and
+/// a lone value is true unless it's empty, "false", or "0".
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub src: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Var(String),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Value(Operand),
+    Cmp(Operand, CmpOp, Operand),
+    Match(Operand, Regex),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "=~", "&&", "||", "<", ">", "!"];
+
+/// whether a character may be part of a variable name,
+/// that is whether it matches `[\w.]`
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = src.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("unclosed string".to_string()),
+                    Some('\\') if i + 1 < chars.len() => {
+                        s.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&other) => {
+                        s.push(other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Literal(s));
+        } else if c == '$' && chars.get(i + 1) == Some(&'{') {
+            // a variable may also be written as in patterns, e.g. ${weight}
+            let start = i + 2;
+            let len = chars[start..].iter()
+                .position(|&c| c == '}')
+                .ok_or_else(|| "unclosed ${".to_string())?;
+            let name: String = chars[start..start + len].iter().collect();
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(is_word_char) {
+                return Err(format!("invalid variable {:?}", name));
+            }
+            tokens.push(Token::Ident(name.to_string()));
+            i = start + len + 1;
+        } else if is_word_char(c) || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+            // a word is a variable, a boolean, or a number, which may be negative
+            let start = i;
+            i += 1;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if word.parse::<f64>().is_ok() || word == "true" || word == "false" {
+                tokens.push(Token::Literal(word));
+            } else if c == '-' {
+                return Err(format!("invalid number {:?}", word));
+            } else {
+                tokens.push(Token::Ident(word));
+            }
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected character {:?}", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.eat_op("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.eat_op("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat_op("!") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            if self.next() != Some(Token::Close) {
+                return Err("missing closing parenthesis".to_string());
+            }
+            return Ok(expr);
+        }
+        self.parse_cmp()
+    }
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Operand::Var(name)),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+    fn parse_cmp(&mut self) -> Result<Expr, String> {
+        let left = self.parse_operand()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            Some(Token::Op("=~")) => {
+                self.pos += 1;
+                return match self.next() {
+                    Some(Token::Literal(src)) => Regex::new(&src)
+                        .map(|regex| Expr::Match(left, regex))
+                        .map_err(|e| e.to_string()),
+                    _ => Err("=~ must be followed by a string".to_string()),
+                };
+            }
+            _ => {
+                return Ok(Expr::Value(left));
+            }
+        };
+        self.pos += 1;
+        let right = self.parse_operand()?;
+        Ok(Expr::Cmp(left, op, right))
+    }
+}
+
+impl Operand {
+    fn value<'p>(&'p self, props: &'p HashMap<String, String>) -> &'p str {
+        match self {
+            Self::Var(name) => props.get(name).map(|s| s.as_str()).unwrap_or(""),
+            Self::Literal(value) => value,
+        }
+    }
+}
+
+fn compare(a: &str, b: &str) -> Option<Ordering> {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b),
+        _ => Some(a.cmp(b)),
+    }
+}
+
+impl Expr {
+    fn eval(&self, props: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Value(operand) => {
+                !matches!(operand.value(props), "" | "false" | "0")
+            }
+            Self::Cmp(left, op, right) => {
+                let ord = compare(left.value(props), right.value(props));
+                match op {
+                    CmpOp::Eq => ord == Some(Ordering::Equal),
+                    CmpOp::Ne => ord != Some(Ordering::Equal),
+                    CmpOp::Lt => ord == Some(Ordering::Less),
+                    CmpOp::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                    CmpOp::Gt => ord == Some(Ordering::Greater),
+                    CmpOp::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+                }
+            }
+            Self::Match(operand, regex) => regex.is_match(operand.value(props)),
+            Self::Not(expr) => !expr.eval(props),
+            Self::And(a, b) => a.eval(props) && b.eval(props),
+            Self::Or(a, b) => a.eval(props) || b.eval(props),
+        }
+    }
+    fn collect_variables<'e>(&'e self, variables: &mut Vec<&'e str>) {
+        let mut add = |operand: &'e Operand| {
+            if let Operand::Var(name) = operand {
+                variables.push(name);
+            }
+        };
+        match self {
+            Self::Value(operand) | Self::Match(operand, _) => add(operand),
+            Self::Cmp(left, _, right) => {
+                add(left);
+                add(right);
+            }
+            Self::Not(expr) => expr.collect_variables(variables),
+            Self::And(a, b) | Self::Or(a, b) => {
+                a.collect_variables(variables);
+                b.collect_variables(variables);
+            }
+        }
+    }
+}
+
+impl Condition {
+    pub fn new(src: &str) -> Result<Self, ConfError> {
+        Self::parse(src)
+            .map(|expr| Self {
+                src: src.to_string(),
+                expr,
+            })
+            .map_err(|reason| ConfError::InvalidCondition {
+                condition: src.to_string(),
+                reason,
+            })
+    }
+    fn parse(src: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {:?}", token));
+        }
+        Ok(expr)
+    }
+    pub fn eval(&self, props: &HashMap<String, String>) -> bool {
+        self.expr.eval(props)
+    }
+    /// the names of the variables used in the condition
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        self.expr.collect_variables(&mut variables);
+        variables
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.src)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let src = String::deserialize(deserializer)?;
+        Self::new(&src).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn eval(src: &str, pairs: &[(&str, &str)]) -> bool {
+        Condition::new(src).unwrap().eval(&props(pairs))
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let p = [("age", "18"), ("status", "active")];
+        assert!(eval("age == 18", &p));
+        assert!(eval("age != 17", &p));
+        assert!(eval("age >= 18", &p));
+        assert!(eval("age <= 18", &p));
+        assert!(!eval("age < 18", &p));
+        assert!(eval("age > -3.5", &p));
+        assert!(eval("${status} == 'active'", &p));
+        assert!(eval(r#"status =~ "^act""#, &p));
+        assert!(!eval("missing == 0", &p));
+        assert!(eval("missing == ''", &p));
+    }
+
+    #[test]
+    fn test_numbers_and_strings() {
+        // numbers are compared as numbers
+        assert!(eval("a < b", &[("a", "9"), ("b", "10")]));
+        assert!(eval("a == b", &[("a", "1.0"), ("b", "1")]));
+        // other values as strings
+        assert!(!eval("a < b", &[("a", "9x"), ("b", "10x")]));
+        assert!(eval("a < b", &[("a", "10"), ("b", "9x")]));
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        let p = [("a", "1"), ("b", "0"), ("c", "false"), ("d", "yes")];
+        assert!(eval("a && d", &p));
+        assert!(!eval("a && b", &p));
+        assert!(eval("b || d", &p));
+        assert!(!eval("b || c", &p));
+        assert!(eval("!b && !c", &p));
+        assert!(!eval("!(a || b)", &p));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let p = [("t", "1"), ("f", "0")];
+        // && binds tighter than ||
+        assert!(eval("t || f && f", &p));
+        assert!(!eval("(t || f) && f", &p));
+        assert!(eval("f && f || t", &p));
+        // ! binds tighter than &&
+        assert!(eval("!f && t", &p));
+        assert!(!eval("!(f || t)", &p));
+        // comparisons bind tighter than boolean operators
+        assert!(eval("t == 1 && f == 0", &p));
+    }
+
+    #[test]
+    fn test_variables() {
+        let condition = Condition::new("a.b == 1 || (c && ${d.e} =~ 'x')").unwrap();
+        assert_eq!(condition.variables(), vec!["a.b", "c", "d.e"]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        for src in [
+            "",
+            "a ==",
+            "== 1",
+            "(a == 1",
+            "a == 1)",
+            "a && || b",
+            "a = 1",
+            "a == 'open",
+            "${a",
+            "${}",
+            "${a b} == 1",
+            "a-b == 1",
+            "a == -1x",
+            "a =~ b",
+            "a =~ '('",
+            "a == 1 b",
+            "a # b",
+        ] {
+            assert!(Condition::new(src).is_err(), "{:?} should be invalid", src);
+        }
+    }
+}
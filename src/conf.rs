@@ -1,29 +1,234 @@
 use {
     crate::*,
+    log::*,
     serde::Deserialize,
+    serde_json::Value,
     std::{
-        path::PathBuf,
+        collections::HashMap,
+        mem,
+        path::{Path, PathBuf},
+        sync::Arc,
     },
 };
 
 
-/// Redis access configuration
-#[derive(Debug, Deserialize)]
-pub struct RedisConf {
-    pub url: String,
-}
-
 /// The configuration of Resc, as read from a JSON file
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Conf {
     pub redis: RedisConf,
+    /// additional Redis servers, referenced by name
+    /// in the `redis` property of `make` elements
+    #[serde(default)]
+    pub redis_servers: HashMap<String, RedisConf>,
     pub listener_channel: String,
+    /// the channel where commands like `pause <watcher>`
+    /// are received, if any
+    pub control_channel: Option<String>,
+    /// the prefix of the keys pausing watchers: while the key made of
+    /// this prefix and a name of a watcher exists, the watcher is paused
+    pub pause_key_prefix: Option<String>,
+    /// when set, each watcher runs on only one of the resc
+    /// instances sharing its Redis server, the leader of its election
+    pub leader_election: Option<LeaderElectionConf>,
+    /// when set, this instance runs only the watchers, and
+    /// discovered queues, assigned to this shard
+    pub shard: Option<Shard>,
+    /// the channel where the lifecycle events of the
+    /// tasks are published as JSON, if any
+    pub events_channel: Option<String>,
+    /// the sorted set holding the delayed tasks until
+    /// they're due, on every Redis server
+    #[serde(default = "default_delayed_set")]
+    pub delayed_set: String,
+    /// whether a variable without value in a make pattern makes
+    /// the rule fail, unless the rule says otherwise
+    #[serde(default)]
+    pub strict_patterns: bool,
+    /// whether the problems found by the check of the configuration
+    /// prevent the scheduler from starting, instead of being logged
+    /// as warnings (as with the --strict argument)
+    #[serde(default)]
+    pub strict: bool,
+    /// whether the incoming tasks are evaluated without anything
+    /// being written, as with the --dry-run argument
+    #[serde(default)]
+    pub dry_run: bool,
+    /// whether the configuration must be reloaded when
+    /// the file changes
+    #[serde(default)]
+    pub auto_reload: bool,
+    /// paths of files whose watchers are added to
+    /// the ones of this file
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    /// parameterized rules, instantiated in the rules of
+    /// the watchers (already done when the conf is read)
+    #[serde(default)]
+    pub templates: HashMap<String, Value>,
+    #[serde(default)]
     pub watchers: Vec<WatcherConf>,
+    /// rules appended to the rules of every watcher
+    /// (already done when the conf is read)
+    #[serde(default)]
+    pub global_rules: Vec<Rule>,
+    /// tasks periodically pushed in queues
+    #[serde(default)]
+    pub generators: Vec<GeneratorConf>,
+    /// where to send metrics, if anywhere
+    pub metrics: Option<MetricsConf>,
+    /// where to answer health checks, if anywhere
+    pub health: Option<HealthConf>,
+    /// where to export traces, if anywhere
+    pub tracing: Option<TracingConf>,
+    /// where to record the produced tasks, if anywhere
+    pub audit: Option<AuditConf>,
+}
+
+impl Conf {
+    /// make all the HTTP fetchers answer with the mocks
+    pub fn set_fetcher_mocks(&mut self, mocks: &Arc<FetcherMocks>) {
+        for watcher in &mut self.watchers {
+            for rule in &mut watcher.rules {
+                for fetcher in &mut rule.fetchers {
+                    fetcher.set_mocks(mocks);
+                }
+            }
+        }
+    }
+}
+
+/// The content of a file included at the top level
+/// of the configuration
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchersFragment {
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    #[serde(default)]
+    pub watchers: Vec<WatcherConf>,
+}
+
+/// The content of a file included in a watcher
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFragment {
+    pub rules: Vec<Rule>,
 }
 
 pub fn read_file(filename: &str) -> Result<Conf, ConfError> {
     let start = std::time::Instant::now();
-    let conf = SerdeFormat::read_file(&PathBuf::from(&filename));
+    let path = PathBuf::from(&filename);
+    let value: Value = SerdeFormat::read_file(&path)?;
+    let templates = RuleTemplates::from_conf(&value)?;
+    let mut conf: Conf = templates.deserialize(&path, value)?;
+    let watchers = mem::take(&mut conf.watchers);
+    let includes = mem::take(&mut conf.include);
+    let mut stack = vec![canonical(path)];
+    for watcher in resolve_includes(watchers, includes, &templates, &mut stack)? {
+        conf.watchers.extend(watcher.split_input_queues()?);
+    }
+    for watcher in &mut conf.watchers {
+        watcher.rules.extend(conf.global_rules.iter().cloned());
+        let watcher_name = watcher.lease_name();
+        for (index, rule) in watcher.rules.iter_mut().enumerate() {
+            rule.debounce_scope = format!("{}/{}", watcher_name, index);
+        }
+        // until its queues are discovered, a watcher's input queue is its pattern
+        let input_queue = watcher.input_queue.as_ref().or(watcher.input_queue_pattern.as_ref());
+        if let Some(queue) = input_queue {
+            for rule in &mut watcher.rules {
+                rule.input_queue = Some(queue.clone());
+            }
+        }
+    }
+    remove_disabled(&mut conf.watchers);
     debug!("Conf read in {:?}", start.elapsed());
-    conf
+    Ok(conf)
+}
+
+pub fn default_enabled() -> bool {
+    true
+}
+
+/// Remove the watchers and the rules which aren't enabled,
+/// logging them
+fn remove_disabled(watchers: &mut Vec<WatcherConf>) {
+    let mut disabled_watchers = Vec::new();
+    watchers.retain(|watcher| {
+        if !watcher.enabled {
+            match watcher.label() {
+                Ok(label) => disabled_watchers.push(label),
+                Err(_) => disabled_watchers.push("<invalid input>".to_string()),
+            }
+        }
+        watcher.enabled
+    });
+    let mut disabled_rules = Vec::new();
+    for watcher in watchers.iter_mut() {
+        watcher.rules.retain(|rule| {
+            if !rule.enabled {
+                disabled_rules.push(format!("{:?}", rule.name));
+            }
+            rule.enabled
+        });
+    }
+    if !disabled_watchers.is_empty() {
+        info!("disabled watchers: {}", disabled_watchers.join(", "));
+    }
+    if !disabled_rules.is_empty() {
+        info!("disabled rules: {}", disabled_rules.join(", "));
+    }
+}
+
+/// Add to the watchers of the file on top of the stack the
+/// rules and watchers of the files they include.
+///
+/// Relative paths are relative to the including file, and the
+/// included files may use the templates of the configuration.
+fn resolve_includes(
+    mut watchers: Vec<WatcherConf>,
+    includes: Vec<PathBuf>,
+    templates: &RuleTemplates,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Vec<WatcherConf>, ConfError> {
+    let dir = stack.last()
+        .and_then(|path| path.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    for watcher in &mut watchers {
+        for include in mem::take(&mut watcher.include) {
+            let path = canonical(dir.join(include));
+            let fragment: RulesFragment = read_included(&path, templates)?;
+            watcher.rules.extend(fragment.rules);
+        }
+    }
+    for include in includes {
+        let path = canonical(dir.join(include));
+        if stack.contains(&path) {
+            return Err(ConfError::IncludeCycle(path));
+        }
+        let fragment: WatchersFragment = read_included(&path, templates)?;
+        stack.push(path);
+        let included = resolve_includes(fragment.watchers, fragment.include, templates, stack)?;
+        stack.pop();
+        watchers.extend(included);
+    }
+    Ok(watchers)
+}
+
+fn read_included<T>(path: &Path, templates: &RuleTemplates) -> Result<T, ConfError>
+    where T: serde::de::DeserializeOwned
+{
+    debug!("reading included file {:?}", path);
+    SerdeFormat::read_file(path)
+        .and_then(|value: Value| templates.deserialize(path, value))
+        .map_err(|source| ConfError::Include {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        })
+}
+
+fn canonical(path: PathBuf) -> PathBuf {
+    path.canonicalize().unwrap_or(path)
 }
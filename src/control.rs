@@ -0,0 +1,123 @@
+use {
+    crate::*,
+    log::*,
+    redis::Msg,
+    std::{
+        collections::HashSet,
+        sync::{mpsc::Sender, Arc, RwLock},
+        thread,
+        time::Duration,
+    },
+};
+
+/// the bounds of the delay before a new subscription
+/// to the control channel, after a failure
+const RESUBSCRIBE_MIN_DELAY: Duration = Duration::from_millis(500);
+const RESUBSCRIBE_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The names of the paused watchers, shared by the
+/// scheduler and the watchers
+#[derive(Debug, Clone, Default)]
+pub struct PausedWatchers {
+    names: Arc<RwLock<HashSet<String>>>,
+}
+
+impl PausedWatchers {
+    /// pause the watchers known by this name, and
+    /// return whether they weren't already paused
+    pub fn pause(&self, name: &str) -> bool {
+        self.names.write().unwrap().insert(name.to_string())
+    }
+    /// resume the watchers known by this name, and
+    /// return whether they were paused
+    pub fn resume(&self, name: &str) -> bool {
+        self.names.write().unwrap().remove(name)
+    }
+    /// whether a watcher known by any of those names is paused
+    pub fn is_paused(&self, names: &[String]) -> bool {
+        let paused = self.names.read().unwrap();
+        names.iter().any(|name| paused.contains(name))
+    }
+}
+
+/// parse a message of the control channel
+pub fn parse_control_command(command: &str) -> Option<SchedulerEvent> {
+    let command = command.trim();
+    let (verb, arg) = match command.split_once(char::is_whitespace) {
+        Some((verb, arg)) => (verb, arg.trim()),
+        None => (command, ""),
+    };
+    match (verb, arg) {
+        ("pause", name) if !name.is_empty() => Some(SchedulerEvent::Pause(name.to_string())),
+        ("resume", name) if !name.is_empty() => Some(SchedulerEvent::Resume(name.to_string())),
+        ("reload", "") => Some(SchedulerEvent::Reload),
+        ("drain", "") => Some(SchedulerEvent::Shutdown),
+        _ => None,
+    }
+}
+
+/// Listens to the commands published on the control
+/// channel, and passes them to the scheduler
+pub struct ControlListener {
+    redis_conf: RedisConf,
+    channel: String,
+    tx: Sender<SchedulerEvent>,
+}
+
+impl ControlListener {
+
+    /// build the listener of the control channel,
+    /// or None when there's none
+    pub fn new(conf: &Conf, tx: Sender<SchedulerEvent>) -> Option<Self> {
+        conf.control_channel.as_ref().map(|channel| Self {
+            redis_conf: conf.redis.clone(),
+            channel: channel.clone(),
+            tx,
+        })
+    }
+
+    /// listen to the control channel until the scheduler
+    /// stops, subscribing again after failures
+    pub fn run(&self) {
+        let mut backoff = Backoff::new(RESUBSCRIBE_MIN_DELAY, RESUBSCRIBE_MAX_DELAY);
+        loop {
+            match self.listen() {
+                Ok(()) => {
+                    return;
+                }
+                Err(e) => {
+                    let delay = backoff.next_delay();
+                    warn!("control channel {:?} failed: {}, subscribing again in {:?}", &self.channel, e, delay);
+                    thread::sleep(delay);
+                }
+            }
+        }
+    }
+
+    /// pass the commands to the scheduler, until it's gone
+    /// (then return Ok) or the connection fails
+    fn listen(&self) -> Result<(), RescError> {
+        let mut con = self.redis_conf.open_pubsub_connection()?;
+        redis::cmd("SUBSCRIBE").arg(&self.channel).query::<()>(&mut con)?;
+        info!("listening to commands on control channel {:?}", &self.channel);
+        loop {
+            let value = con.recv_response()?;
+            let msg = match Msg::from_value(&value) {
+                Some(msg) => msg,
+                None => continue, // a subscription confirmation
+            };
+            let command: String = msg.get_payload()?;
+            let event = match parse_control_command(&command) {
+                Some(event) => event,
+                None => {
+                    warn!("unknown control command {:?}", &command);
+                    continue;
+                }
+            };
+            info!("control command {:?} received", &command);
+            if self.tx.send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
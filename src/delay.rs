@@ -0,0 +1,269 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    redis::{Commands, Script},
+    serde::{Deserialize, Serialize},
+    std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    },
+};
+
+/// how often the delayed sets are checked for due tasks
+const MOVE_PERIOD: Duration = Duration::from_secs(1);
+
+/// the maximal number of tasks moved in one query
+const MOVE_BATCH: isize = 100;
+
+/// A Lua function writing a task to its queue, stream, or sorted set,
+/// then doing its side effects, shared by the scripts writing tasks.
+///
+/// `write(target, task, i)` reads the write from ARGV[i], as encoded by
+/// `write_args`: the kind of write (the push command for a queue, "stream",
+/// or "zadd"), the number of its arguments, its arguments (the fields of
+/// the stream entry, or the score in the sorted set), the number of side
+/// effects, then the command of each effect, preceded by its length.
+pub const WRITE_FUNCTION: &str = r"
+    local function write(target, task, i)
+        local kind = ARGV[i]
+        local n = tonumber(ARGV[i + 1])
+        if kind == 'stream' then
+            redis.call('XADD', target, '*', unpack(ARGV, i + 2, i + 1 + n))
+        elseif kind == 'zadd' then
+            redis.call('ZADD', target, ARGV[i + 2], task)
+        else
+            redis.call(kind, target, task)
+        end
+        i = i + 2 + n
+        local effects = tonumber(ARGV[i])
+        for _ = 1, effects do
+            local len = tonumber(ARGV[i + 1])
+            redis.call(unpack(ARGV, i + 2, i + 1 + len))
+            i = i + 1 + len
+        end
+    end
+";
+
+lazy_static! {
+    // This script makes the removal from the delayed set and the write
+    // to the target atomic, so that a task is written only once even
+    // when several instances of resc move tasks.
+    //
+    // A task with a maximal queue length (ARGV[3]) stays in the delayed
+    // set, rescheduled at ARGV[4], while its queue is too long.
+    static ref PROMOTE_SCRIPT: Script = Script::new(&format!("{}{}", WRITE_FUNCTION, r"
+        if ARGV[3] ~= '' and redis.call('LLEN', KEYS[2]) >= tonumber(ARGV[3]) then
+            redis.call('ZADD', KEYS[1], 'XX', ARGV[4], ARGV[1])
+            return 0
+        end
+        if redis.call('ZREM', KEYS[1], ARGV[1]) == 1 then
+            write(KEYS[2], ARGV[2], 5)
+            return 1
+        end
+        return 0
+    "));
+}
+
+/// the arguments of a write by the `write` Lua
+/// function (see WRITE_FUNCTION)
+pub fn write_args(
+    target: &TaskTarget,
+    push: PushSide,
+    effects: &[Effect],
+) -> Vec<String> {
+    let mut args = Vec::new();
+    match target {
+        TaskTarget::Queue(_) => {
+            args.push(push.command().to_string());
+            args.push("0".to_string());
+        }
+        TaskTarget::Stream { fields, .. } => {
+            args.push("stream".to_string());
+            args.push((2 * fields.len()).to_string());
+            for (key, value) in fields {
+                args.push(key.clone());
+                args.push(value.clone());
+            }
+        }
+        TaskTarget::Zset { score, .. } => {
+            args.push("zadd".to_string());
+            args.push("1".to_string());
+            args.push(score.to_string());
+        }
+    }
+    args.push(effects.len().to_string());
+    for effect in effects {
+        let command = effect.command();
+        args.push(command.len().to_string());
+        args.extend(command);
+    }
+    args
+}
+
+pub fn default_delayed_set() -> String {
+    "resc/delayed".to_string()
+}
+
+/// A task waiting, in the delayed set, for its time to be written.
+///
+/// It's stored as JSON, with a random id making the member unique,
+/// so that identical tasks delayed together are all written, each
+/// at its own time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DelayedTask {
+    /// not in the members written by the previous versions
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub task: String,
+    pub target: TaskTarget,
+    /// not serialized when it's the default, so that the
+    /// members of the previous versions are still the same
+    #[serde(default, skip_serializing_if = "PushSide::is_left")]
+    pub push: PushSide,
+    /// the length of the queue beyond which the task waits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queue_len: Option<usize>,
+    /// the side effects, done when the task is written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<Effect>,
+}
+
+impl DelayedTask {
+    pub fn new(result: &RuleResult) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            task: result.task.clone(),
+            target: result.target.clone(),
+            push: result.push,
+            max_queue_len: result.max_queue_len,
+            effects: result.effects.clone(),
+        }
+    }
+    /// the member of the delayed set
+    pub fn to_member(&self) -> String {
+        serde_json::to_string(self).unwrap() // only strings, can't fail
+    }
+}
+
+/// The mover writes the due tasks of the delayed sets of
+/// some Redis servers to their queue, stream, or sorted set
+pub struct Mover {
+    delayed_set: String,
+    redis_confs: Vec<RedisConf>,
+}
+
+impl Mover {
+
+    /// build the mover of the delayed sets of all the servers a
+    /// configuration may write to, or None when no rule delays tasks
+    /// (tasks are also delayed by rate limits, and parked when their
+    /// queue is too long)
+    pub fn new(conf: &Conf) -> Option<Self> {
+        let delays = conf.watchers.iter()
+            .flat_map(|w| &w.rules)
+            .flat_map(|rule| rule.makers.as_slice())
+            .any(|maker| {
+                maker.delay.is_some()
+                    || maker.rate_limit.is_some()
+                    || maker.max_queue_len.is_some()
+            });
+        if !delays {
+            return None;
+        }
+        let mut redis_confs = vec![conf.redis.clone()];
+        let others = conf.watchers.iter()
+            .filter_map(|w| w.redis.as_ref())
+            .chain(conf.redis_servers.values());
+        for redis_conf in others {
+            if !redis_confs.contains(redis_conf) {
+                redis_confs.push(redis_conf.clone());
+            }
+        }
+        Some(Self {
+            delayed_set: conf.delayed_set.clone(),
+            redis_confs,
+        })
+    }
+
+    /// move the due tasks until `stop` is set.
+    ///
+    /// A server which can't be reached is retried on next period.
+    pub fn run(&self, stop: &AtomicBool) {
+        info!("mover launched on {} server(s)", self.redis_confs.len());
+        let mut cons: Vec<Option<RedisConnection>> = self.redis_confs.iter()
+            .map(|_| None)
+            .collect();
+        while !stop.load(Ordering::Relaxed) {
+            for (redis_conf, con) in self.redis_confs.iter().zip(cons.iter_mut()) {
+                if con.is_none() {
+                    match redis_conf.open_connection() {
+                        Ok(c) => {
+                            *con = Some(c);
+                        }
+                        Err(e) => {
+                            warn!("mover can't connect to redis: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                if let Some(c) = con.as_mut() {
+                    if let Err(e) = self.move_due_tasks(c) {
+                        warn!("moving delayed tasks failed: {}", e);
+                        *con = None;
+                    }
+                }
+            }
+            sleep_unless_stopped(MOVE_PERIOD, stop);
+        }
+        info!("mover stopped");
+    }
+
+    /// write the tasks whose time has come
+    fn move_due_tasks(&self, con: &mut RedisConnection) -> Result<(), RescError> {
+        // a full batch means there may be more due tasks
+        while self.move_due_batch(con)? == MOVE_BATCH as usize {}
+        Ok(())
+    }
+
+    /// write at most a batch of due tasks, and return
+    /// the number of tasks found
+    fn move_due_batch(&self, con: &mut RedisConnection) -> Result<usize, RescError> {
+        let members: Vec<String> = con.zrangebyscore_limit(
+            &self.delayed_set,
+            "-inf",
+            now_secs(),
+            0,
+            MOVE_BATCH,
+        )?;
+        let found = members.len();
+        for member in members {
+            let delayed: DelayedTask = match serde_json::from_str(&member) {
+                Ok(delayed) => delayed,
+                Err(e) => {
+                    warn!("removing invalid delayed task {:?}: {}", &member, e);
+                    let _: () = con.zrem(&self.delayed_set, &member)?;
+                    continue;
+                }
+            };
+            let max_queue_len = match &delayed.target {
+                TaskTarget::Queue(_) => delayed.max_queue_len,
+                _ => None,
+            };
+            let moved: bool = PROMOTE_SCRIPT
+                .key(&self.delayed_set)
+                .key(delayed.target.name())
+                .arg(&member)
+                .arg(&delayed.task)
+                .arg(max_queue_len.map_or_else(String::new, |l| l.to_string()))
+                .arg(now_secs() + MOVE_PERIOD.as_secs_f64())
+                .arg(write_args(&delayed.target, delayed.push, &delayed.effects))
+                .invoke(con)?;
+            if moved {
+                info!("  ->  delayed {:?} pushed to {}", &delayed.task, &delayed.target);
+            }
+        }
+        Ok(found)
+    }
+
+}
@@ -0,0 +1,182 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    regex::Regex,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::Sender,
+            Arc,
+        },
+        thread::JoinHandle,
+        time::Duration,
+    },
+};
+
+/// how often, in seconds, the queues matching the pattern
+/// of a watcher are looked for, when not configured
+pub const DEFAULT_DISCOVERY_INTERVAL: u64 = 10;
+
+/// the number of successive discoveries which must miss a queue,
+/// because it's empty or was deleted, for its watchers to be stopped
+const DISCOVERY_MISSES: usize = 6;
+
+lazy_static! {
+    static ref TAKEN_QUEUE_REGEX: Regex = Regex::new(r"/taken(/\d+)?$").unwrap();
+}
+
+/// The watchers of a discovered queue
+struct DiscoveredQueue {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+    health_states: Vec<WatcherHealth>,
+    /// the number of discoveries since the queue was last found
+    misses: usize,
+}
+
+/// Looks periodically for the queues matching the `input_queue_pattern`
+/// of a watcher, starting watchers for the new ones, and stopping the
+/// ones of the queues which weren't found for a while
+#[derive(Clone)]
+pub struct QueueDiscovery {
+    watcher_conf: WatcherConf,
+    pattern: String,
+    interval: Duration,
+    conf: Arc<Conf>,
+    tx: Sender<SchedulerEvent>,
+    health: Health,
+    paused: PausedWatchers,
+}
+
+impl QueueDiscovery {
+
+    /// build the discovery of the queues of a watcher,
+    /// or None when it has no input_queue_pattern
+    pub fn new(
+        watcher_conf: &WatcherConf,
+        conf: &Arc<Conf>,
+        tx: Sender<SchedulerEvent>,
+        health: Health,
+        paused: PausedWatchers,
+    ) -> Option<Self> {
+        let pattern = watcher_conf.input_queue_pattern.clone()?;
+        let interval = watcher_conf.discovery_interval.unwrap_or(DEFAULT_DISCOVERY_INTERVAL);
+        Some(Self {
+            watcher_conf: watcher_conf.clone(),
+            pattern,
+            interval: Duration::from_secs(interval),
+            conf: Arc::clone(conf),
+            tx,
+            health,
+            paused,
+        })
+    }
+
+    /// discover the queues until `stop` is set, then
+    /// stop the watchers of the discovered queues
+    pub fn run(&self, stop: &AtomicBool) {
+        info!("discovery of the queues matching {:?} launched", &self.pattern);
+        let redis_conf = self.watcher_conf.redis.as_ref().unwrap_or(&self.conf.redis);
+        let mut con: Option<RedisConnection> = None;
+        let mut queues: HashMap<String, DiscoveredQueue> = HashMap::new();
+        while !stop.load(Ordering::Relaxed) {
+            if con.is_none() {
+                match redis_conf.open_connection() {
+                    Ok(c) => {
+                        con = Some(c);
+                    }
+                    Err(e) => {
+                        warn!("queue discovery can't connect to redis: {}", e);
+                    }
+                }
+            }
+            if let Some(c) = con.as_mut() {
+                match self.discover(c) {
+                    Ok(found) => {
+                        if let Err(e) = self.update(&mut queues, found) {
+                            error!("watcher on discovered queue failed: {}", e);
+                            let _ = self.tx.send(SchedulerEvent::WatcherFailed);
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("discovery of the queues matching {:?} failed: {}", &self.pattern, e);
+                        con = None;
+                    }
+                }
+            }
+            sleep_unless_stopped(self.interval, stop);
+        }
+        for (_, discovered) in queues.drain() {
+            self.stop_watchers(discovered);
+        }
+        info!("discovery of the queues matching {:?} stopped", &self.pattern);
+    }
+
+    /// the lists matching the pattern, except the taken queues
+    /// and the ones assigned to other shards
+    fn discover(&self, con: &mut RedisConnection) -> Result<Vec<String>, RescError> {
+        let lists = matching_lists(con, &self.pattern)?;
+        Ok(lists.into_iter()
+            .filter(|list| !TAKEN_QUEUE_REGEX.is_match(list))
+            .filter(|list| self.conf.shard.is_none_or(|shard| shard.owns(list)))
+            .collect())
+    }
+
+    /// start the watchers of the new queues, and stop the ones
+    /// of the queues missed by too many discoveries
+    fn update(
+        &self,
+        queues: &mut HashMap<String, DiscoveredQueue>,
+        found: Vec<String>,
+    ) -> Result<(), RescError> {
+        for discovered in queues.values_mut() {
+            discovered.misses += 1;
+        }
+        for queue in found {
+            if let Some(discovered) = queues.get_mut(&queue) {
+                discovered.misses = 0;
+                continue;
+            }
+            info!("queue {:?} discovered", &queue);
+            let stop = Arc::new(AtomicBool::new(false));
+            let mut handles = Vec::new();
+            let watcher_conf = self.watcher_conf.discovered(&queue);
+            let started = start_watcher(&watcher_conf, &self.conf, &stop, &self.tx, &self.paused, &mut handles);
+            let health_states = match started {
+                Ok(health_states) => health_states,
+                Err(e) => {
+                    self.stop_watchers(DiscoveredQueue { stop, handles, health_states: Vec::new(), misses: 0 });
+                    return Err(e);
+                }
+            };
+            self.health.add_watchers(health_states.clone());
+            queues.insert(queue, DiscoveredQueue { stop, handles, health_states, misses: 0 });
+        }
+        let lost: Vec<String> = queues.iter()
+            .filter(|(_, discovered)| discovered.misses >= DISCOVERY_MISSES)
+            .map(|(queue, _)| queue.clone())
+            .collect();
+        for queue in lost {
+            info!("queue {:?} not found anymore, its watchers are stopped", &queue);
+            if let Some(discovered) = queues.remove(&queue) {
+                self.stop_watchers(discovered);
+            }
+        }
+        Ok(())
+    }
+
+    /// ask the watchers of a queue to stop after their
+    /// current task, and wait for them
+    fn stop_watchers(&self, discovered: DiscoveredQueue) {
+        discovered.stop.store(true, Ordering::Relaxed);
+        for h in discovered.handles {
+            if h.join().is_err() {
+                warn!("a watcher thread panicked");
+            }
+        }
+        self.health.remove_watchers(&discovered.health_states);
+    }
+}
@@ -0,0 +1,66 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    regex::{Captures, Regex},
+    serde_json::Value,
+    std::env,
+};
+
+/// Replace the `${env:SOME_VAR}` tokens of a string
+/// with the values of the environment variables.
+///
+/// The `env:` prefix prevents any confusion with the variables
+/// of patterns, which are expanded later, at task generation.
+pub fn expand_env_vars(src: &str) -> Result<String, ConfError> {
+    lazy_static! {
+        static ref ENV_VAR_REGEX: Regex = Regex::new(r"\$\{env:(\w+)\}").unwrap();
+    }
+    let mut missing = None;
+    let expanded = ENV_VAR_REGEX.replace_all(src, |caps: &Captures| {
+        let name = caps.get(1).unwrap().as_str();
+        match env::var(name) {
+            Ok(value) => value,
+            Err(_) => {
+                missing.get_or_insert_with(|| name.to_string());
+                String::new()
+            }
+        }
+    });
+    match missing {
+        Some(name) => Err(ConfError::MissingEnvVar(name)),
+        None => Ok(expanded.to_string()),
+    }
+}
+
+/// Replace the `${env:SOME_VAR}` tokens in the strings of a
+/// configuration read as a value, and return whether there was any.
+///
+/// As this is done after parsing, the values of the variables
+/// may contain any character.
+pub fn expand_env_vars_in(value: &mut Value) -> Result<bool, ConfError> {
+    match value {
+        Value::String(s) => {
+            let expanded = expand_env_vars(s)?;
+            if expanded == *s {
+                return Ok(false);
+            }
+            *s = expanded;
+            Ok(true)
+        }
+        Value::Array(values) => {
+            let mut any = false;
+            for value in values {
+                any |= expand_env_vars_in(value)?;
+            }
+            Ok(any)
+        }
+        Value::Object(map) => {
+            let mut any = false;
+            for value in map.values_mut() {
+                any |= expand_env_vars_in(value)?;
+            }
+            Ok(any)
+        }
+        _ => Ok(false),
+    }
+}
@@ -2,19 +2,60 @@ use {
     thiserror::Error,
 };
 
+/// The result of the operations of resc
+pub type RescResult<T> = Result<T, RescError>;
 
 #[derive(Error, Debug)]
 pub enum RescError {
 
-    #[error("conf error")]
+    #[error("conf error: {0}")]
     Conf(#[from] ConfError),
 
-    #[error("fetch error")]
+    #[error("fetch error: {0}")]
     Reqwest(#[from] FetchError),
 
-    #[error("redis error")]
+    #[error("redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
+    #[error("no sentinel could give the address of master {0:?}")]
+    MasterNotFound(String),
+
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("a watcher failed")]
+    WatcherFailed,
+
+    #[error("invalid delay {0:?}: a number of seconds was expected")]
+    InvalidDelay(String),
+
+    #[error("invalid score {0:?}: a number was expected")]
+    InvalidScore(String),
+
+    #[error("invalid join count {0:?}: a positive integer was expected")]
+    InvalidJoinCount(String),
+
+    #[error("the task isn't valid JSON: {0}")]
+    InvalidJsonTask(String),
+
+    #[error("no value for variable {variable:?} of pattern {pattern:?}")]
+    MissingVariable {
+        variable: String,
+        pattern: String,
+    },
+
+    #[error("tracing error: {0}")]
+    Tracing(String),
+
+    #[error("transform script failed: {0}")]
+    Transform(String),
+
+    #[error("wasm plugin {path:?} failed: {reason}")]
+    Wasm {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+
 }
 
 #[derive(Error, Debug)]
@@ -26,11 +67,107 @@ pub enum ConfError {
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
 
+    #[error("Environment variable not set: {0:?}")]
+    MissingEnvVar(String),
+
+    #[error("Error in included file {path:?}: {source}")]
+    Include {
+        path: std::path::PathBuf,
+        source: Box<ConfError>,
+    },
+
+    #[error("Cyclic inclusion of {0:?}")]
+    IncludeCycle(std::path::PathBuf),
+
+    #[error("Invalid pattern {pattern:?}: {reason}")]
+    InvalidPattern {
+        pattern: String,
+        reason: String,
+    },
+
+    #[error("Invalid condition {condition:?}: {reason}")]
+    InvalidCondition {
+        condition: String,
+        reason: String,
+    },
+
+    #[error("Unknown rule template: {0:?}")]
+    UnknownTemplate(String),
+
+    #[error("Invalid use of template {template:?}: {reason}")]
+    InvalidTemplateUse {
+        template: String,
+        reason: String,
+    },
+
+    #[error("Invalid transform script: {0}")]
+    InvalidTransform(String),
+
+    #[error("Fetcher type {0:?} is already defined")]
+    FetcherTypeTaken(String),
+
+    #[error("Unknown Redis server: {0:?}")]
+    UnknownRedisServer(String),
+
+    #[error("A make element needs a queue, a stream, or a zset")]
+    MissingMakeTarget,
+
+    #[error("A watcher needs exactly one of input_queue, input_queues, input_queue_pattern, input_stream, input_channel, and input_keyspace")]
+    InvalidWatcherInput,
+
+    #[error("A watcher with input_queues or input_queue_pattern can't have a taken_queue, each queue having its own")]
+    SharedTakenQueue,
+
+    #[error("Only queue and stream watchers can have a concurrency")]
+    ConcurrentChannel,
+
+    #[error("No watcher reads the queue {0:?}")]
+    UnknownInputQueue(String),
+
+    #[error("{0} watchers read a queue, the queue must be given")]
+    InputQueueNeeded(usize),
+
+    #[error("No watcher pushes its unmatched or failed tasks to {0:?}, the queue where to replay them must be given")]
+    UnknownReplaySource(String),
+
+    #[error("Several watchers push their unmatched or failed tasks to {0:?}, the queue where to replay them must be given")]
+    AmbiguousReplayTarget(String),
+
+    #[error("A Redis configuration needs either an url, sentinels, or cluster nodes")]
+    MissingRedisUrl,
+
+    #[error("A Redis configuration with sentinels needs a master_name")]
+    MissingMasterName,
+
+    #[error("A Redis configuration with a client certificate needs both tls_cert and tls_key")]
+    IncompleteTlsClientCert,
+
+    #[error("Can't read the TLS file {path:?}: {source}")]
+    TlsFile {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid configuration: {0}")]
+    Invalid(serde_json::Error),
+
+    #[error("Invalid configuration: {}", .0.join(", "))]
+    Problems(Vec<String>),
+
     #[error("Invalid Hjson: {0}")]
     Hjson(#[from] deser_hjson::Error),
 
     #[error("Invalid JSON: {0}")]
     JSON(#[from] serde_json::Error),
+
+    #[error("Invalid JSON5: {0}")]
+    Json5(#[from] json5::Error),
+
+    #[error("Invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("Invalid YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
 }
 
 
@@ -46,11 +183,45 @@ pub enum FetchError {
     #[error("unexpected response content")]
     UnexpectedContent,
 
+    #[error("extraction failed: {0}")]
+    Extract(String),
+
+    #[error("invalid url of next page: {0:?}")]
+    InvalidNextPage(String),
+
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+
+    #[error("SQL error: {0}")]
+    Sql(String),
+
+    #[error("command error: {0}")]
+    Command(String),
+
+    #[error("environment variable not set: {0:?}")]
+    MissingEnvVar(String),
+
+    #[error("no mock response for url {0:?}")]
+    NoMock(String),
+
     #[error("io error")]
     IO(#[from] std::io::Error),
 
     #[error("invalid JSON")]
     JSON(#[from] serde_json::Error),
 
+    /// an error of a fetcher of a registered type
+    #[error("{0}")]
+    Custom(Box<RescError>),
+
+}
+
+impl From<RescError> for FetchError {
+    fn from(e: RescError) -> Self {
+        match e {
+            RescError::Reqwest(e) => e,
+            e => Self::Custom(Box::new(e)),
+        }
+    }
 }
 
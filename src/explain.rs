@@ -0,0 +1,113 @@
+use {
+    crate::*,
+    serde_json::Value,
+    std::{collections::HashMap, path::Path},
+};
+
+/// read the results of the fetchers given to explain a task: an
+/// object whose keys are `returns` namespaces and whose values are
+/// arrays of results, in any format of configuration files
+pub fn read_fetch_results(path: Option<&str>) -> Result<HashMap<String, Vec<Value>>, ConfError> {
+    match path {
+        Some(path) => SerdeFormat::read_file(Path::new(path)),
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// Explain what the configuration does with a task: the matching
+/// rules, the properties, the queries of the fetchers, and the
+/// tasks which would be made.
+///
+/// Nothing is read nor written in Redis and the fetchers aren't
+/// called: their results are the ones given in `fetch_results`,
+/// by `returns` namespace, or the mocked ones.
+pub fn explain(
+    conf: &Conf,
+    task: &str,
+    fetch_results: &HashMap<String, Vec<Value>>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for watcher in &conf.watchers {
+        match watcher.label() {
+            Ok(label) => lines.push(format!("watcher on {}", label)),
+            Err(e) => lines.push(format!("watcher with invalid input: {}", e)),
+        }
+        let mut rules = watcher.rules.clone();
+        for rule in &mut rules {
+            rule.strict_patterns.get_or_insert(conf.strict_patterns);
+        }
+        let ruleset = Ruleset::new(rules, watcher.match_mode);
+        let matching_rules = ruleset.matching_rules(task);
+        if matching_rules.iter().all(|rule| rule.dry_run) {
+            match &watcher.unmatched_queue {
+                Some(queue) => lines.push(format!("  no matching rule, pushed to queue {:?}", queue)),
+                None => lines.push("  no matching rule".to_string()),
+            }
+        }
+        for rule in matching_rules {
+            let dry_run = if rule.dry_run { " (dry run)" } else { "" };
+            lines.push(format!("  rule {:?} matches{}", rule.name, dry_run));
+            explain_rule(rule, task, fetch_results, &mut lines);
+        }
+    }
+    lines
+}
+
+fn explain_rule(
+    rule: &Rule,
+    task: &str,
+    fetch_results: &HashMap<String, Vec<Value>>,
+    lines: &mut Vec<String>,
+) {
+    let props = match rule.props(task) {
+        Ok(props) => props,
+        Err(e) => {
+            lines.push(format!("    rule fails: {}", e));
+            return;
+        }
+    };
+    let mut names: Vec<&String> = props.keys().collect();
+    names.sort();
+    for name in names {
+        lines.push(format!("    {} = {:?}", name, &props[name]));
+    }
+    let mut all_fetch_results = Vec::new();
+    for (fi, fetcher) in rule.fetchers.iter().enumerate() {
+        for (path, pattern) in fetcher.patterns() {
+            lines.push(format!("    fetch[{}].{}: {:?}", fi, path, pattern.inject(&props)));
+        }
+        let returns = fetcher.returns();
+        if let Some(values) = fetch_results.get(returns) {
+            all_fetch_results.push(values.iter().map(|value| FetchResult::from_json(returns, value)).collect());
+            continue;
+        }
+        match fetcher.mocked_results(&props) {
+            Some(Ok(mocked_results)) => {
+                lines.push(format!("    fetch[{}] mocked: {} result(s)", fi, mocked_results.len()));
+                all_fetch_results.push(mocked_results);
+            }
+            Some(Err(e)) => {
+                lines.push(format!("    fetch[{}] fails: {}", fi, e));
+                return;
+            }
+            None => {
+                lines.push(format!("    no result given for {:?}", returns));
+                all_fetch_results.push(Vec::new());
+            }
+        }
+    }
+    match rule.combine(props, all_fetch_results) {
+        Ok(results) if results.is_empty() => {
+            lines.push("    no task made".to_string());
+        }
+        Ok(results) => {
+            for r in results {
+                let delay = r.delay.map_or_else(String::new, |delay| format!(" in {}s", delay));
+                lines.push(format!("    -> {:?} written to {}{}", &r.task, &r.target, delay));
+            }
+        }
+        Err(e) => {
+            lines.push(format!("    rule fails: {}", e));
+        }
+    }
+}
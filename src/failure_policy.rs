@@ -0,0 +1,66 @@
+use {
+    serde::{de, Deserialize, Deserializer},
+    std::{fmt, str::FromStr},
+};
+
+/// What to do with the task triggering a rule when the rule
+/// fails, because a fetcher failed or a pattern couldn't be
+/// injected
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum FailurePolicy {
+
+    /// log the error and forget about it
+    #[default]
+    Drop,
+
+    /// put the task back in the input, to be handled again later
+    Requeue,
+
+    /// push the task to the given queue
+    DeadLetter(String),
+
+    /// try the rule again, at most this number of times,
+    /// before dropping the task
+    Retry(usize),
+
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "drop" => Ok(Self::Drop),
+            None if s == "requeue" => Ok(Self::Requeue),
+            Some(("dead_letter", queue)) if !queue.is_empty() => {
+                Ok(Self::DeadLetter(queue.to_string()))
+            }
+            Some(("retry", n)) => n.parse()
+                .map(Self::Retry)
+                .map_err(|_| format!("invalid number of retries: {:?}", n)),
+            _ => Err(format!(
+                "invalid on_failure {:?}, expected drop, requeue, dead_letter:<queue>, or retry:<n>",
+                s,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drop => write!(f, "drop"),
+            Self::Requeue => write!(f, "requeue"),
+            Self::DeadLetter(queue) => write!(f, "dead_letter:{}", queue),
+            Self::Retry(n) => write!(f, "retry:{}", n),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FailurePolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,95 @@
+use {
+    crate::*,
+    serde::Deserialize,
+    std::{convert::TryFrom, env},
+};
+
+/// The authentication of the requests of a fetcher.
+///
+/// The secrets are read from environment variables, so that
+/// they're not in the configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "FetchAuthConf")]
+pub enum FetchAuth {
+
+    /// an `Authorization: Bearer` header, with the token
+    /// read from the given environment variable
+    Bearer { token_env: String },
+
+    /// basic authentication, with the password, if any, read
+    /// from the given environment variable
+    Basic { username: String, password_env: Option<String> },
+
+}
+
+/// the auth object, as written in the configuration
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FetchAuthConf {
+    bearer_env: Option<String>,
+    username: Option<String>,
+    password_env: Option<String>,
+}
+
+impl TryFrom<FetchAuthConf> for FetchAuth {
+    type Error = &'static str;
+    fn try_from(conf: FetchAuthConf) -> Result<Self, Self::Error> {
+        match (conf.bearer_env, conf.username, conf.password_env) {
+            (Some(token_env), None, None) => Ok(Self::Bearer { token_env }),
+            (None, Some(username), password_env) => Ok(Self::Basic { username, password_env }),
+            _ => Err("an auth element needs either a bearer_env, or a username and an optional password_env"),
+        }
+    }
+}
+
+fn read_env(name: &str) -> Result<String, FetchError> {
+    env::var(name).map_err(|_| FetchError::MissingEnvVar(name.to_string()))
+}
+
+impl FetchAuth {
+    /// add the authentication to the request
+    pub fn apply(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, FetchError> {
+        Ok(match self {
+            Self::Bearer { token_env } => {
+                request.bearer_auth(read_env(token_env)?)
+            }
+            Self::Basic { username, password_env } => {
+                let password = password_env.as_deref().map(read_env).transpose()?;
+                request.basic_auth(username, password)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<FetchAuth, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert!(matches!(
+            parse(r#"{"bearer_env":"TOKEN"}"#),
+            Ok(FetchAuth::Bearer { token_env }) if token_env == "TOKEN"
+        ));
+        assert!(matches!(
+            parse(r#"{"username":"resc","password_env":"PASS"}"#),
+            Ok(FetchAuth::Basic { username, password_env: Some(pass) })
+                if username == "resc" && pass == "PASS"
+        ));
+        assert!(matches!(
+            parse(r#"{"username":"resc"}"#),
+            Ok(FetchAuth::Basic { password_env: None, .. })
+        ));
+        assert!(parse(r#"{"bearer_env":"TOKEN","username":"resc"}"#).is_err());
+        assert!(parse(r#"{"password_env":"PASS"}"#).is_err());
+        assert!(parse("{}").is_err());
+        assert!(parse(r#"{"bearer":"TOKEN"}"#).is_err());
+    }
+}
@@ -1,9 +1,9 @@
 use {
     crate::*,
     log::*,
-    serde::Deserialize,
-    serde_json::{self, Value},
-    std::{collections::HashMap, io::Read},
+    serde::{de, Deserialize, Deserializer},
+    serde_json::{Map, Value},
+    std::{collections::HashMap, fmt, sync::Arc},
 };
 
 /// the data the fetcher got
@@ -12,70 +12,205 @@ pub struct FetchResult {
     pub props: HashMap<String, String>,
 }
 
-/// A Fetcher is responsible for synchronously fetching some data
-/// (for use in handling a rule)
-#[derive(Debug, Clone, Deserialize)]
-pub struct Fetcher {
-    pub url: Pattern,
-    pub returns: String,
-}
-
-impl Fetcher {
-    fn returned_key(&self, key: &str) -> String {
-        format!("{}.{}", self.returns, key)
-    }
-
-    fn get_fetch_result(&self, object_value: &serde_json::Map<String, Value>) -> FetchResult {
+impl FetchResult {
+    /// the result of a JSON object, whose string and number
+    /// properties are read in the `returns` namespace
+    pub fn from_object(returns: &str, object_value: &Map<String, Value>) -> Self {
         let mut props = HashMap::new();
         for (key, value) in object_value {
             match value {
                 Value::String(string_value) => {
-                    props.insert(self.returned_key(key), string_value.to_owned());
+                    props.insert(format!("{}.{}", returns, key), string_value.to_owned());
                 }
                 Value::Number(number_value) => {
-                    props.insert(self.returned_key(key), number_value.to_string());
+                    props.insert(format!("{}.{}", returns, key), number_value.to_string());
                 }
                 _ => {
                     debug!(" ignoring property {:#?}={:#?}", key, value);
                 }
             }
         }
-        FetchResult { props }
+        Self { props }
+    }
+    /// the result of a single value, available as the
+    /// `value` property of the `returns` namespace
+    pub fn from_value(returns: &str, value: String) -> Self {
+        let mut props = HashMap::new();
+        props.insert(format!("{}.value", returns), value);
+        Self { props }
+    }
+    /// the result of a JSON value, an object or a single value,
+    /// as given to explain or test a rule
+    pub fn from_json(returns: &str, value: &Value) -> Self {
+        match value {
+            Value::Object(object) => Self::from_object(returns, object),
+            Value::String(s) => Self::from_value(returns, s.clone()),
+            _ => Self::from_value(returns, value.to_string()),
+        }
     }
+}
+
+/// what a fetcher may need, besides the properties,
+/// to fetch its data
+pub struct FetchContext<'c> {
+    /// the connection of the watcher
+    pub con: &'c mut RedisConnection,
+    /// where to send metrics, if configured
+    pub metrics: Option<&'c Metrics>,
+    /// the span of the rule being applied
+    pub span: &'c TraceSpan,
+    /// whether nothing must be written
+    pub dry_run: bool,
+}
+
+/// A kind of fetcher which doesn't need the connection of the
+/// watcher, and may thus run on any thread.
+///
+/// Besides the built-in ones, kinds of fetchers may be defined by
+/// the programs using resc as a library, and registered with
+/// `register_fetcher_type`.
+pub trait Fetch: fmt::Debug + Send + Sync {
+    /// the namespace of the properties of the results
+    fn returns(&self) -> &str;
+    /// the patterns of the fetcher, with their paths
+    /// in the fetcher's configuration
+    fn patterns(&self) -> Vec<(String, Pattern)> {
+        Vec::new()
+    }
+    /// fetch the data, the properties of the task being
+    /// the ones to inject in the patterns
+    fn results(&self, props: &HashMap<String, String>) -> RescResult<Vec<FetchResult>>;
+}
 
-    pub fn results(&self, props: &HashMap<String, String>) -> Result<Vec<FetchResult>, FetchError> {
-        let url = self.url.inject(props);
-        info!("  querying url: {:#?}", url);
-        let mut response = reqwest::get(&url)?;
-        if !response.status().is_success() {
-            return Err(FetchError::ErrorStatus(response.status().into()));
+/// A Fetcher is responsible for synchronously fetching some data
+/// (for use in handling a rule).
+///
+/// Its kind is given by the `type` property, "http" by default.
+#[derive(Debug, Clone)]
+pub enum Fetcher {
+    Http(Box<HttpFetcher>),
+    Redis(RedisFetcher),
+    Command(CommandFetcher),
+    #[cfg(any(feature = "postgres", feature = "mysql"))]
+    Sql(SqlFetcher),
+    Wasm(WasmFetcher),
+    /// a fetcher of a registered type
+    Custom(Arc<dyn Fetch>),
+}
+
+impl Fetcher {
+    /// the fetcher as a `Fetch`, or the redis fetcher,
+    /// which needs the connection of the watcher
+    fn standalone(&self) -> Result<&dyn Fetch, &RedisFetcher> {
+        match self {
+            Self::Http(fetcher) => Ok(fetcher.as_ref()),
+            Self::Redis(fetcher) => Err(fetcher),
+            Self::Command(fetcher) => Ok(fetcher),
+            #[cfg(any(feature = "postgres", feature = "mysql"))]
+            Self::Sql(fetcher) => Ok(fetcher),
+            Self::Wasm(fetcher) => Ok(fetcher),
+            Self::Custom(fetcher) => Ok(fetcher.as_ref()),
         }
-        // TODO use derive for response deserialization
-        let mut json = String::new();
-        response.read_to_string(&mut json)?;
-        let mut results = Vec::new();
-        let value: Value = serde_json::from_str(&json)?;
-        // we accept either a simple object, or an array of objects
-        match value {
-            Value::Array(returned_values) => {
-                for returned_value in &returned_values {
-                    match returned_value {
-                        Value::Object(object_value) => {
-                            results.push(self.get_fetch_result(object_value));
-                        }
-                        _ => {
-                            return Err(FetchError::UnexpectedContent);
-                        }
-                    }
-                }
-            }
-            Value::Object(returned_value) => {
-                results.push(self.get_fetch_result(&returned_value));
-            }
-            _ => {
-                return Err(FetchError::UnexpectedContent);
-            }
+    }
+    /// the namespace of the properties of the results
+    pub fn returns(&self) -> &str {
+        match self.standalone() {
+            Ok(fetcher) => fetcher.returns(),
+            Err(fetcher) => &fetcher.returns,
+        }
+    }
+    /// the patterns of the fetcher, with their paths
+    /// in the fetcher's configuration
+    pub fn patterns(&self) -> Vec<(String, Pattern)> {
+        match self.standalone() {
+            Ok(fetcher) => fetcher.patterns(),
+            Err(fetcher) => vec![("key".to_string(), fetcher.key.clone())],
+        }
+    }
+    /// make the fetcher answer with the mocks instead of
+    /// querying, if it's an HTTP fetcher
+    pub fn set_mocks(&mut self, mocks: &Arc<FetcherMocks>) {
+        if let Self::Http(fetcher) = self {
+            fetcher.mocks = Some(Arc::clone(mocks));
+        }
+    }
+    /// fetch, if the fetcher answers with mocks, without
+    /// network access
+    pub fn mocked_results(
+        &self,
+        props: &HashMap<String, String>,
+    ) -> Option<Result<Vec<FetchResult>, FetchError>> {
+        match self {
+            Self::Http(fetcher) if fetcher.mocks.is_some() => Some(fetcher.results(props)),
+            _ => None,
+        }
+    }
+    /// whether the fetcher needs the connection of the watcher
+    pub fn uses_connection(&self) -> bool {
+        matches!(self, Self::Redis(_))
+    }
+    /// fetch, when the fetcher doesn't need the connection
+    /// of the watcher, and may thus run on any thread
+    pub fn standalone_results(
+        &self,
+        props: &HashMap<String, String>,
+    ) -> Option<Result<Vec<FetchResult>, FetchError>> {
+        self.standalone()
+            .ok()
+            .map(|fetcher| fetcher.results(props).map_err(FetchError::from))
+    }
+    pub fn results(
+        &self,
+        props: &HashMap<String, String>,
+        context: &mut FetchContext<'_>,
+    ) -> Result<Vec<FetchResult>, FetchError> {
+        match self {
+            Self::Redis(fetcher) => fetcher.results(props, context),
+            _ => self.standalone_results(props).unwrap(), // only redis fetchers need the context
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Fetcher {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let mut value = Value::deserialize(deserializer)?;
+        let fetcher_type = match value.as_object_mut().and_then(|o| o.remove("type")) {
+            Some(Value::String(s)) => s,
+            Some(_) => return Err(de::Error::custom("the type of a fetcher must be a string")),
+            None => "http".to_string(),
+        };
+        match fetcher_type.as_str() {
+            "http" => HttpFetcher::deserialize(value)
+                .map(|fetcher| Self::Http(Box::new(fetcher)))
+                .map_err(de::Error::custom),
+            "redis" => RedisFetcher::deserialize(value)
+                .map(Self::Redis)
+                .map_err(de::Error::custom),
+            "command" => CommandFetcher::deserialize(value)
+                .map(Self::Command)
+                .map_err(de::Error::custom),
+            #[cfg(any(feature = "postgres", feature = "mysql"))]
+            "sql" => SqlFetcher::deserialize(value)
+                .map(Self::Sql)
+                .map_err(de::Error::custom),
+            #[cfg(not(any(feature = "postgres", feature = "mysql")))]
+            "sql" => Err(de::Error::custom(
+                "sql fetchers need resc to be compiled with the postgres or mysql feature"
+            )),
+            "wasm" => WasmFetcher::deserialize(value)
+                .map(Self::Wasm)
+                .map_err(de::Error::custom),
+            _ => match build_custom_fetcher(&fetcher_type, value) {
+                Some(fetcher) => fetcher
+                    .map(Self::Custom)
+                    .map_err(de::Error::custom),
+                None => Err(de::Error::custom(format!(
+                    "unknown fetcher type {:?}, expected \"http\", \"redis\", \"command\", \"sql\", \"wasm\", or a registered type",
+                    fetcher_type,
+                ))),
+            },
         }
-        Ok(results)
     }
 }
@@ -0,0 +1,36 @@
+use {
+    crate::*,
+    serde::Deserialize,
+    serde_json::Value,
+    std::path::Path,
+};
+
+/// A canned response, given to the HTTP fetchers
+/// querying an url matching one of the regexes
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetcherMock {
+    pub url: RegexList,
+    pub response: Value,
+}
+
+/// The content of the file given with --mock-fetchers, whose
+/// responses replace the queries of the HTTP fetchers
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetcherMocks {
+    pub mocks: Vec<FetcherMock>,
+}
+
+impl FetcherMocks {
+    pub fn read_file(path: &str) -> Result<Self, ConfError> {
+        SerdeFormat::read_file(Path::new(path))
+    }
+    /// the response of the first mock whose regex matches the url
+    pub fn response(&self, url: &str) -> Result<&Value, FetchError> {
+        self.mocks.iter()
+            .find(|mock| mock.url.is_match(url))
+            .map(|mock| &mock.response)
+            .ok_or_else(|| FetchError::NoMock(url.to_string()))
+    }
+}
@@ -0,0 +1,48 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    serde::de::DeserializeOwned,
+    serde_json::Value,
+    std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    },
+};
+
+/// the types of fetchers which can't be registered
+const BUILT_IN_FETCHER_TYPES: &[&str] = &["http", "redis", "command", "sql", "wasm"];
+
+/// builds a fetcher from its configuration, without the `type`
+type FetcherBuilder = Box<dyn Fn(Value) -> Result<Arc<dyn Fetch>, String> + Send + Sync>;
+
+lazy_static! {
+    static ref FETCHER_TYPES: RwLock<HashMap<String, FetcherBuilder>> = RwLock::new(HashMap::new());
+}
+
+/// Register a type of fetcher, usable in the configuration
+/// with `type: <name>`, the other properties of the fetcher
+/// being deserialized into a `F`.
+///
+/// The types must be registered before the configuration is read.
+pub fn register_fetcher_type<F>(name: &str) -> Result<(), ConfError>
+    where F: Fetch + DeserializeOwned + 'static
+{
+    let mut types = FETCHER_TYPES.write().unwrap();
+    if BUILT_IN_FETCHER_TYPES.contains(&name) || types.contains_key(name) {
+        return Err(ConfError::FetcherTypeTaken(name.to_string()));
+    }
+    let builder: FetcherBuilder = Box::new(|value| {
+        F::deserialize(value)
+            .map(|fetcher| Arc::new(fetcher) as Arc<dyn Fetch>)
+            .map_err(|e| e.to_string())
+    });
+    types.insert(name.to_string(), builder);
+    Ok(())
+}
+
+/// build a fetcher of a registered type, or return None
+/// when no type of this name was registered
+pub fn build_custom_fetcher(name: &str, value: Value) -> Option<Result<Arc<dyn Fetch>, String>> {
+    let types = FETCHER_TYPES.read().unwrap();
+    types.get(name).map(|builder| builder(value))
+}
@@ -0,0 +1,175 @@
+use {
+    crate::*,
+    chrono::{DateTime, Local},
+    cron::Schedule,
+    log::*,
+    redis::Commands,
+    serde::{de, Deserialize, Deserializer},
+    std::{
+        str::FromStr,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    },
+};
+
+/// how often the generators check whether a task is due
+const GENERATOR_PERIOD: Duration = Duration::from_secs(1);
+
+/// A generator periodically pushes a task in a queue, usually
+/// the input queue of a watcher
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorConf {
+
+    /// an optional name, for logs
+    pub name: Option<String>,
+
+    /// when to generate the task, as a cron expression in local
+    /// time, e.g. "0 2 * * *" for every night at 2 AM. A field
+    /// for the seconds may be given before the minutes.
+    #[serde(deserialize_with = "deserialize_schedule")]
+    pub cron: Schedule,
+
+    /// the task to generate
+    pub task: String,
+
+    /// the queue where the task is pushed
+    pub queue: String,
+
+    /// the Redis server of the queue, when not the global one
+    pub redis: Option<RedisConf>,
+
+}
+
+impl GeneratorConf {
+    fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.task)
+    }
+}
+
+fn deserialize_schedule<'de, D>(deserializer: D) -> Result<Schedule, D::Error>
+    where D: Deserializer<'de>
+{
+    let src = String::deserialize(deserializer)?;
+    parse_schedule(&src).map_err(de::Error::custom)
+}
+
+fn parse_schedule(src: &str) -> Result<Schedule, String> {
+    // the cron crate wants the seconds, which most users don't give
+    let expr = if src.split_whitespace().count() == 5 {
+        format!("0 {}", src)
+    } else {
+        src.to_string()
+    };
+    Schedule::from_str(&expr)
+        .map_err(|e| format!("invalid cron expression {:?}: {}", src, e))
+}
+
+/// whether a generator whose next time is `next` is due at `now`,
+/// in which case `next` becomes its first time after `now`, so that
+/// the missed times aren't all generated
+fn take_due(cron: &Schedule, next: &mut Option<DateTime<Local>>, now: DateTime<Local>) -> bool {
+    if next.is_some_and(|next| next <= now) {
+        *next = cron.after(&now).next();
+        true
+    } else {
+        false
+    }
+}
+
+/// The running generators of a configuration
+#[derive(Clone)]
+pub struct Generators {
+    generators: Vec<GeneratorConf>,
+    redis_conf: RedisConf,
+}
+
+impl Generators {
+
+    /// build the generators of the configuration, or None when
+    /// there's none
+    pub fn new(conf: &Conf) -> Option<Self> {
+        if conf.generators.is_empty() {
+            return None;
+        }
+        Some(Self {
+            generators: conf.generators.clone(),
+            redis_conf: conf.redis.clone(),
+        })
+    }
+
+    /// generate the tasks when they're due, until `stop` is set.
+    ///
+    /// A task which can't be pushed, for example because
+    /// Redis is down, isn't generated again before its next time.
+    pub fn run(&self, stop: &AtomicBool) {
+        info!("{} generator(s) launched", self.generators.len());
+        let mut nexts: Vec<Option<DateTime<Local>>> = self.generators.iter()
+            .map(|g| g.cron.upcoming(Local).next())
+            .collect();
+        while !stop.load(Ordering::Relaxed) {
+            let now = Local::now();
+            for (generator, next) in self.generators.iter().zip(nexts.iter_mut()) {
+                if take_due(&generator.cron, next, now) {
+                    if let Err(e) = self.generate(generator) {
+                        error!("generator {:?} failed: {}", generator.label(), e);
+                    }
+                }
+            }
+            sleep_unless_stopped(GENERATOR_PERIOD, stop);
+        }
+        info!("generators stopped");
+    }
+
+    fn generate(&self, generator: &GeneratorConf) -> Result<(), RescError> {
+        let redis_conf = generator.redis.as_ref().unwrap_or(&self.redis_conf);
+        let mut con = redis_conf.open_connection()?;
+        let _: () = con.lpush(&generator.queue, &generator.task)?;
+        info!(
+            "generator {:?} pushed {:?} to queue {:?}",
+            generator.label(), &generator.task, &generator.queue,
+        );
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        chrono::TimeZone,
+    };
+
+    fn time(h: u32, m: u32, s: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 1, h, m, s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        // without seconds, the task is generated at the start of the minute
+        let cron = parse_schedule("*/15 2 * * *").unwrap();
+        let times: Vec<_> = cron.after(&time(1, 50, 0)).take(3).collect();
+        assert_eq!(times, vec![time(2, 0, 0), time(2, 15, 0), time(2, 30, 0)]);
+        // with seconds
+        let cron = parse_schedule("30 0 2 * * *").unwrap();
+        assert_eq!(cron.after(&time(1, 50, 0)).next(), Some(time(2, 0, 30)));
+        assert!(parse_schedule("0 25 * * *").is_err());
+        assert!(parse_schedule("every day").is_err());
+    }
+
+    #[test]
+    fn test_take_due() {
+        let cron = parse_schedule("*/10 * * * *").unwrap();
+        let mut next = cron.after(&time(2, 0, 0)).next();
+        assert_eq!(next, Some(time(2, 10, 0)));
+        assert!(!take_due(&cron, &mut next, time(2, 9, 59)));
+        assert_eq!(next, Some(time(2, 10, 0)));
+        assert!(take_due(&cron, &mut next, time(2, 10, 0)));
+        assert_eq!(next, Some(time(2, 20, 0)));
+        // after a pause, the task is generated once, not once per missed time
+        assert!(take_due(&cron, &mut next, time(3, 5, 0)));
+        assert_eq!(next, Some(time(3, 10, 0)));
+        assert!(!take_due(&cron, &mut next, time(3, 6, 0)));
+    }
+}
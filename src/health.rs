@@ -0,0 +1,109 @@
+use {
+    crate::*,
+    log::*,
+    serde::Deserialize,
+    std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+            Mutex,
+        },
+        thread,
+        time::Duration,
+    },
+};
+
+/// Configuration of the HTTP server answering health checks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConf {
+    /// the address to listen on, e.g. "0.0.0.0:8080"
+    pub address: String,
+}
+
+/// A label of a watcher, and whether it's running and connected
+pub type WatcherHealth = (String, Arc<AtomicBool>);
+
+/// The state of the running watchers, as seen by the health checks
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    watchers: Arc<Mutex<Vec<WatcherHealth>>>,
+}
+
+impl Health {
+    /// replace the watchers whose state is checked
+    pub fn set_watchers(&self, watchers: Vec<WatcherHealth>) {
+        *self.watchers.lock().unwrap() = watchers;
+    }
+
+    /// add watchers whose state is checked
+    pub fn add_watchers(&self, watchers: Vec<WatcherHealth>) {
+        self.watchers.lock().unwrap().extend(watchers);
+    }
+
+    /// stop checking the state of watchers
+    pub fn remove_watchers(&self, watchers: &[WatcherHealth]) {
+        self.watchers.lock().unwrap()
+            .retain(|(_, up)| !watchers.iter().any(|(_, removed)| Arc::ptr_eq(up, removed)));
+    }
+
+    /// the labels of the watchers which aren't running or
+    /// not connected
+    fn down_watchers(&self) -> Vec<String> {
+        self.watchers.lock().unwrap().iter()
+            .filter(|(_, up)| !up.load(Ordering::Relaxed))
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    /// start answering, in a dedicated thread, the `/healthz`
+    /// and `/readyz` requests
+    pub fn serve(&self, conf: &HealthConf) -> Result<(), RescError> {
+        let listener = TcpListener::bind(&conf.address)?;
+        info!("health checks served on {}", &conf.address);
+        let health = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = health.answer(stream) {
+                            debug!("health check not answered: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("health check connection failed: {}", e);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let path = request_line.split_whitespace().nth(1).unwrap_or("");
+        let (status, body) = match path {
+            "/healthz" => ("200 OK", "ok".to_string()),
+            "/readyz" => {
+                let down = self.down_watchers();
+                if down.is_empty() {
+                    ("200 OK", "ready".to_string())
+                } else {
+                    ("503 Service Unavailable", format!("not ready: {}", down.join(", ")))
+                }
+            }
+            _ => ("404 Not Found", "not found".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body,
+        )
+    }
+}
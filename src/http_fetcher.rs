@@ -0,0 +1,290 @@
+use {
+    crate::*,
+    log::*,
+    jsonpath_lib::Compiled,
+    serde::{de, Deserialize, Deserializer},
+    serde_json::{self, Value},
+    std::{
+        collections::HashMap,
+        io::Read,
+        sync::Arc,
+        thread,
+        time::Duration,
+    },
+};
+
+/// the maximal delay between two attempts of a fetch
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The HTTP method of the requests of a fetcher
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum FetchMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+/// a request, with all patterns injected
+struct FetchRequest {
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Value>,
+}
+
+/// a successful response
+struct FetchResponse {
+    body: String,
+    /// the value of the `Link` header, if any
+    link: Option<String>,
+}
+
+/// A fetcher querying a web service
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpFetcher {
+    pub url: Pattern,
+    pub returns: String,
+
+    /// the number of new attempts when the request fails
+    /// with a network error or a status of `retry_on_status`
+    #[serde(default)]
+    pub retries: usize,
+
+    /// the delay before the first new attempt, doubled on
+    /// each following one
+    #[serde(default = "HttpFetcher::default_backoff_ms")]
+    pub backoff_ms: u64,
+
+    /// the response statuses which are worth a new attempt
+    #[serde(default = "HttpFetcher::default_retry_on_status")]
+    pub retry_on_status: Vec<u16>,
+
+    /// the maximal duration of a request, in milliseconds.
+    /// When not set, the timeout of the HTTP client (30s) applies.
+    pub timeout_ms: Option<u64>,
+
+    /// the headers of the request, whose values are patterns
+    #[serde(default)]
+    pub headers: HashMap<String, Pattern>,
+
+    /// the authentication of the requests
+    pub auth: Option<FetchAuth>,
+
+    /// the HTTP method, GET or POST
+    #[serde(default)]
+    pub method: FetchMethod,
+
+    /// the body of the request, a JSON value whose strings are patterns
+    pub body: Option<JsonTemplate>,
+
+    /// a JSONPath expression selecting the elements of the response
+    /// which are the results, e.g. "$.data.items[*]"
+    #[serde(default, deserialize_with = "deserialize_json_path")]
+    pub extract: Option<Compiled>,
+
+    /// how to follow the pages of a paginated response
+    pub pagination: Option<Pagination>,
+
+    /// the canned responses replacing the queries, when
+    /// resc is launched with --mock-fetchers
+    #[serde(skip)]
+    pub mocks: Option<Arc<FetcherMocks>>,
+}
+
+pub fn deserialize_json_path<'de, D>(deserializer: D) -> Result<Option<Compiled>, D::Error>
+    where D: Deserializer<'de>
+{
+    let src = String::deserialize(deserializer)?;
+    Compiled::compile(&src)
+        .map(Some)
+        .map_err(|e| de::Error::custom(format!("invalid JSONPath {:?}: {}", src, e)))
+}
+
+impl HttpFetcher {
+    pub fn default_backoff_ms() -> u64 {
+        500
+    }
+    pub fn default_retry_on_status() -> Vec<u16> {
+        vec![502, 503, 504]
+    }
+
+    /// push the results found in the value, according to `extract`
+    fn extract_results(&self, value: &Value, results: &mut Vec<FetchResult>) -> Result<(), FetchError> {
+        match &self.extract {
+            Some(path) => {
+                let selected = path.select(value)
+                    .map_err(|e| FetchError::Extract(format!("{:?}", e)))?;
+                for value in selected {
+                    match value {
+                        Value::String(s) => {
+                            results.push(FetchResult::from_value(&self.returns, s.to_owned()));
+                        }
+                        Value::Number(n) => {
+                            results.push(FetchResult::from_value(&self.returns, n.to_string()));
+                        }
+                        _ => {
+                            self.push_results(value, results)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            None => self.push_results(value, results),
+        }
+    }
+
+    /// push the results read in a JSON value, which must be
+    /// either a simple object, or an array of objects
+    fn push_results(&self, value: &Value, results: &mut Vec<FetchResult>) -> Result<(), FetchError> {
+        match value {
+            Value::Array(returned_values) => {
+                for returned_value in returned_values {
+                    match returned_value {
+                        Value::Object(object_value) => {
+                            results.push(FetchResult::from_object(&self.returns, object_value));
+                        }
+                        _ => {
+                            return Err(FetchError::UnexpectedContent);
+                        }
+                    }
+                }
+            }
+            Value::Object(returned_value) => {
+                results.push(FetchResult::from_object(&self.returns, returned_value));
+            }
+            _ => {
+                return Err(FetchError::UnexpectedContent);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_retryable(&self, error: &FetchError) -> bool {
+        match error {
+            FetchError::Reqwest(_) | FetchError::IO(_) => true,
+            FetchError::ErrorStatus(status) => self.retry_on_status.contains(status),
+            _ => false,
+        }
+    }
+
+    /// query the url, trying again on transient errors
+    fn fetch(&self, request: &FetchRequest) -> Result<FetchResponse, FetchError> {
+        let mut backoff = Backoff::new(Duration::from_millis(self.backoff_ms), RETRY_MAX_DELAY);
+        let attempts = self.retries + 1;
+        for attempt in 1.. {
+            match self.fetch_once(request) {
+                Err(e) if attempt < attempts && self.is_retryable(&e) => {
+                    let delay = backoff.next_delay();
+                    warn!(
+                        "  fetch attempt {}/{} of {:?} failed: {}, next one in {:?}",
+                        attempt, attempts, &request.url, e, delay,
+                    );
+                    thread::sleep(delay);
+                }
+                res => {
+                    return res;
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    fn fetch_once(&self, fetch_request: &FetchRequest) -> Result<FetchResponse, FetchError> {
+        let mut client = reqwest::Client::builder();
+        if let Some(timeout_ms) = self.timeout_ms {
+            client = client.timeout(Duration::from_millis(timeout_ms));
+        }
+        let client = client.build()?;
+        let url = fetch_request.url.as_str();
+        let mut request = match self.method {
+            FetchMethod::Get => client.get(url),
+            FetchMethod::Post => client.post(url),
+        };
+        if let Some(body) = &fetch_request.body {
+            request = request.json(body);
+        }
+        for (name, value) in &fetch_request.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(auth) = &self.auth {
+            request = auth.apply(request)?;
+        }
+        let mut response = request.send()?;
+        if !response.status().is_success() {
+            return Err(FetchError::ErrorStatus(response.status().into()));
+        }
+        let link = response.headers()
+            .get(reqwest::header::LINK)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        // TODO use derive for response deserialization
+        let mut body = String::new();
+        response.read_to_string(&mut body)?;
+        Ok(FetchResponse { body, link })
+    }
+
+    pub fn results(&self, props: &HashMap<String, String>) -> Result<Vec<FetchResult>, FetchError> {
+        let mut request = FetchRequest {
+            url: self.url.inject(props),
+            headers: self.headers.iter()
+                .map(|(name, value)| (name.clone(), value.inject(props)))
+                .collect(),
+            body: self.body.as_ref().map(|body| body.inject(props)),
+        };
+        let mut results = Vec::new();
+        for page in 1.. {
+            let response = match &self.mocks {
+                Some(mocks) => {
+                    info!("  mocking url: {:#?}", &request.url);
+                    FetchResponse {
+                        body: mocks.response(&request.url)?.to_string(),
+                        link: None,
+                    }
+                }
+                None => {
+                    info!("  querying url: {:#?}", &request.url);
+                    self.fetch(&request)?
+                }
+            };
+            let value: Value = serde_json::from_str(&response.body)?;
+            self.extract_results(&value, &mut results)?;
+            let pagination = match &self.pagination {
+                Some(pagination) => pagination,
+                None => break,
+            };
+            match pagination.next_url(&request.url, &value, response.link.as_deref())? {
+                Some(_) if page >= pagination.max_pages => {
+                    warn!("  not fetching more than {} pages", pagination.max_pages);
+                    break;
+                }
+                Some(url) => {
+                    request.url = url;
+                }
+                None => break,
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl Fetch for HttpFetcher {
+    fn returns(&self) -> &str {
+        &self.returns
+    }
+    fn patterns(&self) -> Vec<(String, Pattern)> {
+        let mut patterns = vec![("url".to_string(), self.url.clone())];
+        for (name, value) in &self.headers {
+            patterns.push((format!("headers.{}", name), value.clone()));
+        }
+        if let Some(body) = &self.body {
+            for pattern in body.patterns() {
+                patterns.push(("body".to_string(), pattern));
+            }
+        }
+        patterns
+    }
+    fn results(&self, props: &HashMap<String, String>) -> RescResult<Vec<FetchResult>> {
+        Ok(HttpFetcher::results(self, props)?)
+    }
+}
@@ -0,0 +1,31 @@
+use {
+    crate::*,
+    log::*,
+    redis::Commands,
+};
+
+/// Push a task in the input queue of a watcher, as a producer would,
+/// and publish it on the listener channel. Return the queue.
+///
+/// The queue may be omitted when only one watcher reads a queue.
+pub fn inject(conf: &Conf, queue: Option<&str>, task: &str) -> Result<String, RescError> {
+    let queue_watchers: Vec<&WatcherConf> = conf.watchers.iter()
+        .filter(|watcher| watcher.input_queue.is_some())
+        .collect();
+    let watcher = match queue {
+        Some(queue) => queue_watchers.iter()
+            .find(|watcher| watcher.input_queue.as_deref() == Some(queue))
+            .ok_or_else(|| ConfError::UnknownInputQueue(queue.to_string()))?,
+        None if queue_watchers.len() == 1 => &queue_watchers[0],
+        None => {
+            return Err(ConfError::InputQueueNeeded(queue_watchers.len()).into());
+        }
+    };
+    let queue = watcher.input_queue.clone().unwrap(); // filtered above
+    let redis_conf = watcher.redis.as_ref().unwrap_or(&conf.redis);
+    let mut con = redis_conf.open_connection()?;
+    let _: () = con.lpush(&queue, task)?;
+    let _: () = con.publish(&conf.listener_channel, format!("{} INJECT {}", &queue, task))?;
+    info!("{:?} pushed to queue {:?}", task, &queue);
+    Ok(queue)
+}
@@ -0,0 +1,495 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    redis::{
+        self,
+        streams::{StreamReadOptions, StreamReadReply},
+        Commands,
+        Connection,
+        ConnectionLike,
+        Msg,
+        Pipeline,
+        RedisResult,
+        Script,
+    },
+    std::{
+        collections::{HashSet, VecDeque},
+        fmt,
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// how often a peeked queue is read again, when
+/// no new task was found
+const PEEK_PERIOD: Duration = Duration::from_millis(100);
+
+/// the maximal number of tasks read at the end of a peeked queue
+const PEEK_MAX: isize = 1000;
+
+lazy_static! {
+    // This script requeues the stale tasks of a taken queue atomically,
+    // so that a task acknowledged meanwhile isn't requeued. As the move
+    // of a task to the taken queue and the record of its time are two
+    // commands, a task without time may have just been taken: it's
+    // given the current time, to be requeued only when it's stale. When
+    // the watcher starts, no task is being taken, so a task without time
+    // was taken by a previous run, and is requeued.
+    //
+    // KEYS: the queue, the taken queue, the taken times, the taken retries
+    // ARGV: the limit time, the current time, whether retries are counted,
+    // whether the tasks without time are stale
+    static ref REQUEUE_STALE_SCRIPT: Script = Script::new(r"
+        local requeued = {}
+        for _, task in ipairs(redis.call('LRANGE', KEYS[2], 0, -1)) do
+            local time = redis.call('ZSCORE', KEYS[3], task)
+            if not time and ARGV[4] ~= '1' then
+                redis.call('ZADD', KEYS[3], ARGV[2], task)
+            elseif not time or tonumber(time) <= tonumber(ARGV[1]) then
+                if redis.call('LREM', KEYS[2], 1, task) > 0 then
+                    redis.call('LPUSH', KEYS[1], task)
+                    redis.call('ZREM', KEYS[3], task)
+                    if ARGV[3] == '1' then
+                        redis.call('HINCRBY', KEYS[4], task, 1)
+                    end
+                    table.insert(requeued, task)
+                end
+            end
+        end
+        return requeued
+    ");
+}
+
+/// An event taken from the input of a watcher
+#[derive(Debug)]
+pub struct InputEvent {
+    /// the task, as given to the rules
+    pub task: String,
+    /// the id of the stream entry, when the input is a stream
+    pub entry_id: Option<String>,
+}
+
+/// Where a watcher takes its events
+pub enum Input {
+
+    /// a list, from which events are atomically moved
+    /// to a taken queue while they're handled
+    Queue {
+        queue: String,
+        taken_queue: String, // can't be shared between watchers
+        /// the sorted set keeping the times the tasks of the
+        /// taken queue were taken, when they're tracked
+        taken_times: Option<String>,
+        /// the hash counting, per task, how many times it was
+        /// requeued because not acknowledged in time
+        taken_retries: Option<String>,
+    },
+
+    /// a stream, read by a consumer of a consumer group,
+    /// with the events acknowledged once handled
+    Stream {
+        stream: String,
+        group: String,
+        consumer: String,
+        /// the field of the entries holding the task
+        field: String,
+        /// whether the entries delivered but not acknowledged
+        /// before a restart must still be read
+        reading_pending: bool,
+    },
+
+    /// channels, given by a pattern, whose messages are the tasks.
+    ///
+    /// There's no taken queue: the messages published while
+    /// the watcher isn't subscribed are lost.
+    Channel {
+        pattern: String,
+        /// when the channels are the ones of keyspace notifications,
+        /// the filter of those notifications
+        keyspace: Option<KeyspaceConf>,
+        /// the connection dedicated to the subscription
+        con: Option<Connection>,
+    },
+
+    /// a list whose tasks are read without being taken, in dry run.
+    ///
+    /// The tasks are the ones found at the end of the list which
+    /// weren't there when it was previously read, so the tasks taken
+    /// by another consumer before being read are missed.
+    Peek {
+        queue: String,
+        /// the tasks found on the previous read
+        seen: HashSet<String>,
+        /// the new tasks found, not yet given
+        pending: VecDeque<String>,
+    },
+
+    /// a stream whose new entries are read without
+    /// consumer group, in dry run
+    StreamTail {
+        stream: String,
+        field: String,
+        /// the id of the last entry read
+        last_id: String,
+    },
+
+}
+
+impl Input {
+
+    /// the name used as prefix of the messages on the listener channel
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Queue { taken_queue, .. } => taken_queue,
+            Self::Stream { stream, .. } => stream,
+            Self::Channel { pattern, .. } => pattern,
+            Self::Peek { queue, .. } => queue,
+            Self::StreamTail { stream, .. } => stream,
+        }
+    }
+
+    /// the input reading the same events without taking
+    /// them, nor writing anything, for a dry run
+    pub fn into_dry_run(self) -> Self {
+        match self {
+            Self::Queue { queue, .. } => Self::Peek {
+                queue,
+                seen: HashSet::new(),
+                pending: VecDeque::new(),
+            },
+            Self::Stream { stream, field, .. } => Self::StreamTail {
+                stream,
+                field,
+                last_id: "$".to_string(),
+            },
+            channel => channel,
+        }
+    }
+
+    /// prepare the input, and make sure the events which were being
+    /// handled when the watcher stopped are handled again.
+    ///
+    /// When `stale_age` is given, only the tasks of the taken queue
+    /// taken more than `stale_age` seconds ago are moved back.
+    pub fn recover(
+        &mut self,
+        con: &mut RedisConnection,
+        redis_conf: &RedisConf,
+        stale_age: Option<f64>,
+    ) -> Result<(), RescError> {
+        match self {
+            Self::Queue { queue, taken_queue, taken_times, .. } if stale_age.is_none() => {
+                debug!("watcher cleans its taken queue");
+                let n = restore_taken(con, queue, taken_queue, taken_times.as_deref());
+                if n > 0 {
+                    warn!("moved {} tasks from  {:?} to {:?}", n, taken_queue, queue);
+                }
+            }
+            Self::Queue { .. } => {
+                debug!("watcher requeues the stale tasks of its taken queue");
+                self.requeue_stale(con, stale_age.unwrap_or_default(), true)?;
+            }
+            Self::Stream { stream, group, reading_pending, .. } => {
+                let created: RedisResult<()> = con.xgroup_create_mkstream(&*stream, &*group, "$");
+                match created {
+                    Ok(()) => {
+                        info!("consumer group {:?} created on stream {:?}", group, stream);
+                    }
+                    Err(e) if e.code() == Some("BUSYGROUP") => {} // the group already exists
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                }
+                *reading_pending = true;
+            }
+            Self::Channel { pattern, keyspace, con } => {
+                let mut pubsub_con = redis_conf.open_pubsub_connection()?;
+                if let Some(keyspace) = keyspace {
+                    let db = keyspace.db.unwrap_or_else(|| pubsub_con.get_db());
+                    *pattern = format!("__keyspace@{}__:{}", db, &keyspace.keys);
+                }
+                redis::cmd("PSUBSCRIBE").arg(&*pattern).query::<()>(&mut pubsub_con)?;
+                info!("subscribed to {:?}", pattern);
+                *con = Some(pubsub_con);
+            }
+            Self::Peek { .. } | Self::StreamTail { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// move back to the queue the tasks of the taken queue which
+    /// were taken more than `max_age` seconds ago, returning their
+    /// number. The tasks whose time isn't known are moved back when
+    /// `untimed_stale` is true (that is when the watcher starts), and
+    /// otherwise considered taken now.
+    ///
+    /// This is a no-op unless the input is a queue whose taken
+    /// times are tracked.
+    pub fn requeue_stale(
+        &self,
+        con: &mut RedisConnection,
+        max_age: f64,
+        untimed_stale: bool,
+    ) -> RedisResult<usize> {
+        let (queue, taken_queue, taken_times, taken_retries) = match self {
+            Self::Queue { queue, taken_queue, taken_times: Some(taken_times), taken_retries } => {
+                (queue, taken_queue, taken_times, taken_retries)
+            }
+            _ => {
+                return Ok(0);
+            }
+        };
+        let now = now_secs();
+        let requeued: Vec<String> = REQUEUE_STALE_SCRIPT
+            .key(queue)
+            .key(taken_queue)
+            .key(taken_times)
+            // the taken times stand for the retries when they're not counted
+            .key(taken_retries.as_ref().unwrap_or(taken_times))
+            .arg(now - max_age)
+            .arg(now)
+            .arg(if taken_retries.is_some() { "1" } else { "0" })
+            .arg(if untimed_stale { "1" } else { "0" })
+            .invoke(con)?;
+        for task in &requeued {
+            warn!("stale task {:?} moved from {:?} to {:?}", task, taken_queue, queue);
+        }
+        Ok(requeued.len())
+    }
+
+    /// give back the events taken but not handled, so that they're
+    /// not stranded while the watcher isn't running.
+    ///
+    /// Pending entries of a stream stay pending, to be read again
+    /// by the same consumer.
+    pub fn release(&mut self, con: &mut RedisConnection) {
+        match self {
+            Self::Queue { queue, taken_queue, taken_times, .. } => {
+                let n = restore_taken(con, queue, taken_queue, taken_times.as_deref());
+                if n > 0 {
+                    info!("moved {} tasks from  {:?} back to {:?}", n, taken_queue, queue);
+                }
+            }
+            Self::Stream { .. } | Self::Peek { .. } | Self::StreamTail { .. } => {}
+            Self::Channel { con, .. } => {
+                *con = None;
+            }
+        }
+    }
+
+    /// wait, at most `timeout` seconds, for the next event
+    pub fn next_event(
+        &mut self,
+        con: &mut RedisConnection,
+        timeout: usize,
+    ) -> RedisResult<Option<InputEvent>> {
+        match self {
+            Self::Queue { queue, taken_queue, taken_times, .. } => {
+                // the Direction enum of the blmove command isn't public
+                // in the redis crate
+                let task: Option<String> = redis::cmd("BLMOVE")
+                    .arg(&*queue)
+                    .arg(&*taken_queue)
+                    .arg("RIGHT")
+                    .arg("LEFT")
+                    .arg(timeout)
+                    .query(con)?;
+                if let (Some(task), Some(taken_times)) = (&task, taken_times) {
+                    let _: () = con.zadd(&*taken_times, task, now_secs())?;
+                }
+                Ok(task.map(|task| InputEvent { task, entry_id: None }))
+            }
+            Self::Stream { stream, group, consumer, field, reading_pending } => {
+                loop {
+                    // the pending entries are read with the "0" id, the new ones with ">"
+                    let id = if *reading_pending { "0" } else { ">" };
+                    let options = StreamReadOptions::default()
+                        .group(&*group, &*consumer)
+                        .count(1)
+                        .block(timeout * 1000);
+                    let reply: Option<StreamReadReply> = con.xread_options(&[&*stream], &[id], &options)?;
+                    let entry = reply
+                        .and_then(|reply| reply.keys.into_iter().next())
+                        .and_then(|key| key.ids.into_iter().next());
+                    let entry = match entry {
+                        Some(entry) => entry,
+                        None if *reading_pending => {
+                            debug!("no more pending entry in stream {:?}", stream);
+                            *reading_pending = false;
+                            continue;
+                        }
+                        None => {
+                            return Ok(None);
+                        }
+                    };
+                    match entry.get::<String>(field) {
+                        Some(task) => {
+                            return Ok(Some(InputEvent { task, entry_id: Some(entry.id) }));
+                        }
+                        None => {
+                            warn!("entry {:?} of stream {:?} has no field {:?}", &entry.id, stream, field);
+                            let _: () = con.xack(&*stream, &*group, &[&entry.id])?;
+                        }
+                    }
+                }
+            }
+            Self::Channel { con: pubsub_con, keyspace, .. } => {
+                let pubsub_con = pubsub_con.as_mut()
+                    .ok_or_else(|| redis::RedisError::from((redis::ErrorKind::ClientError, "not subscribed")))?;
+                pubsub_con.set_read_timeout(Some(Duration::from_secs(timeout as u64)))?;
+                loop {
+                    let value = match pubsub_con.recv_response() {
+                        Ok(value) => value,
+                        Err(e) if e.is_timeout() => {
+                            return Ok(None);
+                        }
+                        Err(e) => {
+                            return Err(e);
+                        }
+                    };
+                    let msg = match Msg::from_value(&value) {
+                        Some(msg) => msg,
+                        None => continue, // a subscription confirmation
+                    };
+                    let payload: String = msg.get_payload()?;
+                    let channel = msg.get_channel_name();
+                    debug!("message received on channel {:?}", channel);
+                    let task = match keyspace {
+                        Some(keyspace) => {
+                            // the payload is the event, the key is in the channel
+                            if !keyspace.accepts(&payload) {
+                                continue;
+                            }
+                            match channel.split_once("__:") {
+                                Some((_, key)) => key.to_string(),
+                                None => continue,
+                            }
+                        }
+                        None => payload,
+                    };
+                    return Ok(Some(InputEvent { task, entry_id: None }));
+                }
+            }
+            Self::Peek { queue, seen, pending } => {
+                let deadline = Instant::now() + Duration::from_secs(timeout as u64);
+                loop {
+                    if let Some(task) = pending.pop_front() {
+                        return Ok(Some(InputEvent { task, entry_id: None }));
+                    }
+                    let tasks: Vec<String> = con.lrange(&*queue, -PEEK_MAX, -1)?;
+                    // the tasks are taken from the right end
+                    for task in tasks.iter().rev() {
+                        if !seen.contains(task) {
+                            pending.push_back(task.clone());
+                        }
+                    }
+                    *seen = tasks.into_iter().collect();
+                    if pending.is_empty() {
+                        if Instant::now() >= deadline {
+                            return Ok(None);
+                        }
+                        thread::sleep(PEEK_PERIOD);
+                    }
+                }
+            }
+            Self::StreamTail { stream, field, last_id } => {
+                loop {
+                    let options = StreamReadOptions::default()
+                        .count(1)
+                        .block(timeout * 1000);
+                    let reply: Option<StreamReadReply> = con.xread_options(&[&*stream], &[&*last_id], &options)?;
+                    let entry = reply
+                        .and_then(|reply| reply.keys.into_iter().next())
+                        .and_then(|key| key.ids.into_iter().next());
+                    let entry = match entry {
+                        Some(entry) => entry,
+                        None => {
+                            return Ok(None);
+                        }
+                    };
+                    *last_id = entry.id.clone();
+                    match entry.get::<String>(field) {
+                        Some(task) => {
+                            return Ok(Some(InputEvent { task, entry_id: Some(entry.id) }));
+                        }
+                        None => {
+                            warn!("entry {:?} of stream {:?} has no field {:?}", &entry.id, stream, field);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// put the event back at the end of the input, when it's
+    /// possible, and return whether it was done
+    pub fn requeue(&self, con: &mut RedisConnection, event: &InputEvent) -> RedisResult<bool> {
+        match self {
+            Self::Queue { queue, .. } => {
+                let _: () = con.lpush(queue, &event.task)?;
+                Ok(true)
+            }
+            Self::Stream { stream, field, .. } => {
+                let _: () = con.xadd(stream, "*", &[(field, &event.task)])?;
+                Ok(true)
+            }
+            Self::Channel { .. } | Self::Peek { .. } | Self::StreamTail { .. } => Ok(false),
+        }
+    }
+
+    /// acknowledge a completely handled event, by adding to the pipeline
+    /// the commands removing it from the taken queue, or acknowledging
+    /// it in the stream
+    pub fn ack(&self, pipe: &mut Pipeline, event: &InputEvent) {
+        match self {
+            Self::Queue { taken_queue, taken_times, taken_retries, .. } => {
+                pipe.lrem(taken_queue, 1, &event.task);
+                if let Some(taken_times) = taken_times {
+                    pipe.zrem(taken_times, &event.task);
+                }
+                if let Some(taken_retries) = taken_retries {
+                    pipe.hdel(taken_retries, &event.task);
+                }
+            }
+            Self::Stream { stream, group, .. } => {
+                let id = event.entry_id.as_deref().unwrap_or_default();
+                pipe.xack(stream, group, &[id]);
+            }
+            Self::Channel { .. } | Self::Peek { .. } | Self::StreamTail { .. } => {}
+        }
+    }
+
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Queue { queue, .. } => write!(f, "queue {:?}", queue),
+            Self::Stream { stream, .. } => write!(f, "stream {:?}", stream),
+            Self::Channel { pattern, .. } => write!(f, "channels {:?}", pattern),
+            Self::Peek { queue, .. } => write!(f, "queue {:?} (peeked)", queue),
+            Self::StreamTail { stream, .. } => write!(f, "stream {:?} (tail)", stream),
+        }
+    }
+}
+
+/// move the tasks of a taken queue back to the queue,
+/// returning their number
+fn restore_taken(
+    con: &mut RedisConnection,
+    queue: &str,
+    taken_queue: &str,
+    taken_times: Option<&str>,
+) -> usize {
+    let mut n = 0;
+    let lmove = redis::cmd("LMOVE").arg(taken_queue).arg(queue).arg("RIGHT").arg("LEFT").clone();
+    while let Ok(taken) = lmove.query::<String>(con) {
+        debug!(" moving {:?} from {:?} to {:?}", &taken, taken_queue, queue);
+        n += 1;
+    }
+    if let Some(taken_times) = taken_times {
+        let _: RedisResult<()> = con.del(taken_times);
+    }
+    n
+}
@@ -0,0 +1,73 @@
+use {
+    crate::*,
+    log::*,
+    std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    },
+};
+
+/// how often the janitor looks for timed out tasks
+const JANITOR_PERIOD: Duration = Duration::from_secs(1);
+
+/// The janitor of a queue watcher moves back to the input queue
+/// the tasks which stayed in the taken queue longer than the
+/// `taken_timeout`, making the taken queue a visibility-timeout
+/// buffer.
+///
+/// It runs on its own thread and connection, so that it also
+/// works while the watcher is stuck on a task.
+pub struct Janitor {
+    input: Input,
+    redis_conf: RedisConf,
+    taken_timeout: f64,
+}
+
+impl Janitor {
+
+    /// build the janitor of a slot of a watcher, or None when
+    /// the watcher has no taken timeout
+    pub fn new(
+        watcher_conf: &WatcherConf,
+        slot: usize,
+        global_conf: &Conf,
+    ) -> Result<Option<Self>, RescError> {
+        let taken_timeout = match watcher_conf.taken_timeout {
+            Some(taken_timeout) => taken_timeout,
+            None => {
+                return Ok(None);
+            }
+        };
+        Ok(Some(Self {
+            input: watcher_conf.slot_input(slot)?,
+            redis_conf: watcher_conf.redis.as_ref().unwrap_or(&global_conf.redis).clone(),
+            taken_timeout,
+        }))
+    }
+
+    /// requeue the timed out tasks until `stop` is set
+    pub fn run(&self, stop: &AtomicBool) {
+        info!("janitor launched on {}", &self.input);
+        let mut con: Option<RedisConnection> = None;
+        while !stop.load(Ordering::Relaxed) {
+            if con.is_none() {
+                match self.redis_conf.open_connection() {
+                    Ok(c) => {
+                        con = Some(c);
+                    }
+                    Err(e) => {
+                        warn!("janitor can't connect to redis: {}", e);
+                    }
+                }
+            }
+            if let Some(c) = con.as_mut() {
+                if let Err(e) = self.input.requeue_stale(c, self.taken_timeout, false) {
+                    warn!("looking for timed out tasks in {} failed: {}", &self.input, e);
+                    con = None;
+                }
+            }
+            sleep_unless_stopped(JANITOR_PERIOD, stop);
+        }
+        info!("janitor on {} stopped", &self.input);
+    }
+}
@@ -0,0 +1,101 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    redis::Script,
+    serde::{de, Deserialize, Deserializer},
+    serde_json::Value,
+};
+
+lazy_static! {
+    // Records a parent (ARGV[3]) in the set of the parents of a join,
+    // so that a parent handled twice is counted once, and returns 1
+    // when the set holds the number of parents (ARGV[1]).
+    //
+    // A complete set isn't removed here but in the transaction writing
+    // the task, so that, when this transaction fails, the event handled
+    // again completes the join again.
+    //
+    // ARGV[2] is the ttl of the set, in seconds (0 for none).
+    static ref ARRIVE_SCRIPT: Script = Script::new(r"
+        redis.call('SADD', KEYS[1], ARGV[3])
+        if redis.call('SCARD', KEYS[1]) >= tonumber(ARGV[1]) then
+            return 1
+        end
+        if tonumber(ARGV[2]) > 0 then
+            redis.call('EXPIRE', KEYS[1], ARGV[2])
+        end
+        return 0
+    ");
+    static ref PARENT: Pattern = Pattern::new("${input_task}").unwrap();
+}
+
+/// The definition, in a make element, of a join: the task
+/// is written only when `count` parents produced it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JoinConf {
+
+    /// the key of the set of the parents already there
+    pub key: Pattern,
+
+    /// the number of parents, given as a number or a pattern
+    #[serde(deserialize_with = "deserialize_count")]
+    pub count: Pattern,
+
+    /// the duration, in seconds, after which an incomplete
+    /// join is forgotten, counted from its last parent
+    pub ttl: Option<u64>,
+
+}
+
+fn deserialize_count<'de, D>(deserializer: D) -> Result<Pattern, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => Pattern::new(&n.to_string()).map_err(de::Error::custom),
+        Value::String(s) => Pattern::new(&s).map_err(de::Error::custom),
+        _ => Err(de::Error::custom("the count of a join must be a number or a pattern")),
+    }
+}
+
+/// A join, with its patterns injected
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub key: String,
+    /// the input task producing this task
+    pub parent: String,
+    pub count: u64,
+    pub ttl: Option<u64>,
+}
+
+impl JoinConf {
+    pub fn join(
+        &self,
+        inject: impl Fn(&Pattern) -> Result<String, RescError>,
+    ) -> Result<Join, RescError> {
+        let count = inject(&self.count)?;
+        match count.trim().parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Join {
+                key: inject(&self.key)?,
+                parent: inject(&PARENT)?,
+                count: n,
+                ttl: self.ttl,
+            }),
+            _ => Err(RescError::InvalidJoinCount(count)),
+        }
+    }
+}
+
+impl Join {
+    /// record the parent, and return whether it's the last one, in
+    /// which case the set must be removed with the write of the task
+    pub fn arrive(&self, con: &mut RedisConnection) -> Result<bool, RescError> {
+        let complete: i32 = ARRIVE_SCRIPT
+            .key(&self.key)
+            .arg(self.count)
+            .arg(self.ttl.unwrap_or(0))
+            .arg(&self.parent)
+            .invoke(con)?;
+        Ok(complete == 1)
+    }
+}
@@ -0,0 +1,83 @@
+use {
+    crate::*,
+    serde::Deserialize,
+    serde_json::{Map, Value},
+    std::{collections::HashMap, convert::TryFrom},
+};
+
+/// A JSON value whose strings, keys included, are patterns
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "Value")]
+pub struct JsonTemplate {
+    value: Value,
+    /// the patterns of the strings, in the order
+    /// they're met when walking the value
+    patterns: Vec<Pattern>,
+}
+
+impl TryFrom<Value> for JsonTemplate {
+    type Error = ConfError;
+    /// build the template, parsing its patterns
+    fn try_from(value: Value) -> Result<Self, ConfError> {
+        let mut patterns = Vec::new();
+        collect_patterns(&value, &mut patterns)?;
+        Ok(Self { value, patterns })
+    }
+}
+
+impl JsonTemplate {
+    /// build the JSON value, with all patterns injected
+    pub fn inject(&self, props: &HashMap<String, String>) -> Value {
+        inject_value(&self.value, &mut self.patterns.iter(), props)
+    }
+    /// the patterns of the template
+    pub fn patterns(&self) -> Vec<Pattern> {
+        self.patterns.clone()
+    }
+}
+
+/// inject the properties in a value, taking the patterns
+/// of its strings in the order of collect_patterns
+fn inject_value<'p>(
+    value: &Value,
+    patterns: &mut impl Iterator<Item = &'p Pattern>,
+    props: &HashMap<String, String>,
+) -> Value {
+    // there's a pattern for each string, keys included
+    match value {
+        Value::String(_) => Value::String(patterns.next().unwrap().inject(props)),
+        Value::Array(values) => Value::Array(
+            values.iter().map(|v| inject_value(v, patterns, props)).collect()
+        ),
+        Value::Object(object) => {
+            let mut injected = Map::new();
+            for value in object.values() {
+                let key = patterns.next().unwrap().inject(props);
+                injected.insert(key, inject_value(value, patterns, props));
+            }
+            Value::Object(injected)
+        }
+        _ => value.clone(),
+    }
+}
+
+fn collect_patterns(value: &Value, patterns: &mut Vec<Pattern>) -> Result<(), ConfError> {
+    match value {
+        Value::String(s) => {
+            patterns.push(Pattern::new(s)?);
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_patterns(value, patterns)?;
+            }
+        }
+        Value::Object(object) => {
+            for (key, value) in object {
+                patterns.push(Pattern::new(key)?);
+                collect_patterns(value, patterns)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    redis::Script,
+    serde::Deserialize,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::Sender,
+            Arc,
+        },
+        thread::JoinHandle,
+        time::{Duration, Instant},
+    },
+};
+
+/// the duration, in seconds, of a lease, when not configured
+const DEFAULT_LEASE: f64 = 10.0;
+
+lazy_static! {
+    // Those scripts check the lease is still held by this instance
+    // before extending or releasing it, so that a lease which expired
+    // and was taken by another instance isn't touched.
+    static ref RENEW_SCRIPT: Script = Script::new(r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+        end
+        return 0
+    ");
+    static ref RELEASE_SCRIPT: Script = Script::new(r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+    ");
+}
+
+/// Configuration of the election, among the resc instances sharing
+/// a Redis server, of the one running each watcher
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderElectionConf {
+    /// the prefix of the lease keys, followed by the
+    /// input of the watcher (default: "resc/leader/")
+    #[serde(default = "default_lease_key_prefix")]
+    pub key_prefix: String,
+    /// how long, in seconds, a lease lasts when it's not
+    /// renewed, e.g. because its holder died (default: 10)
+    pub lease: Option<f64>,
+    /// the identifier of this instance in the leases
+    /// (default: the host name, the pid, and a random part)
+    pub instance: Option<String>,
+}
+
+pub fn default_lease_key_prefix() -> String {
+    "resc/leader/".to_string()
+}
+
+impl LeaderElectionConf {
+    pub fn lease_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.lease.unwrap_or(DEFAULT_LEASE).max(0.1))
+    }
+    /// the identifier of this instance, which must be
+    /// the same in all the elections of the instance
+    pub fn instance(&self) -> String {
+        match &self.instance {
+            Some(instance) => instance.clone(),
+            None => {
+                lazy_static! {
+                    static ref INSTANCE: String = format!(
+                        "{}-{}-{}",
+                        hostname::get().map_or_else(|_| "resc".into(), |h| h.to_string_lossy().to_string()),
+                        std::process::id(),
+                        &uuid::Uuid::new_v4().simple().to_string()[..8],
+                    );
+                }
+                INSTANCE.clone()
+            }
+        }
+    }
+}
+
+/// A lease in Redis, held by at most one instance
+#[derive(Debug)]
+struct Lease {
+    key: String,
+    instance: String,
+    duration: Duration,
+}
+
+impl Lease {
+    /// extend the lease if it's held by this instance, or take it if
+    /// it's held by nobody, and return whether this instance holds it
+    fn renew_or_acquire(&self, con: &mut RedisConnection) -> Result<bool, RescError> {
+        let ms = self.duration.as_millis() as u64;
+        let renewed: i64 = RENEW_SCRIPT
+            .key(&self.key)
+            .arg(&self.instance)
+            .arg(ms)
+            .invoke(con)?;
+        if renewed == 1 {
+            return Ok(true);
+        }
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&self.key)
+            .arg(&self.instance)
+            .arg("NX")
+            .arg("PX")
+            .arg(ms)
+            .query(con)?;
+        Ok(acquired.is_some())
+    }
+    /// give up the lease, if it's held by this instance
+    fn release(&self, con: &mut RedisConnection) -> Result<(), RescError> {
+        let _: i64 = RELEASE_SCRIPT
+            .key(&self.key)
+            .arg(&self.instance)
+            .invoke(con)?;
+        Ok(())
+    }
+}
+
+/// Start threads, returning the states of the watchers
+/// among them for the health checks
+pub type Starter = Box<dyn Fn(&Arc<AtomicBool>, &mut Vec<JoinHandle<()>>) -> Result<Vec<WatcherHealth>, RescError> + Send>;
+
+/// The threads started while this instance is the leader
+struct Leading {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+    health_states: Vec<WatcherHealth>,
+}
+
+/// Runs threads, e.g. the ones of a watcher, only while this instance
+/// holds their lease, so that, among the resc instances sharing a Redis
+/// server, only one runs them, another one taking over when its lease
+/// expires.
+///
+/// The lease is renewed three times per duration. The threads are
+/// stopped when the lease is taken by another instance, or when it
+/// couldn't be renewed for half its duration.
+pub struct Election {
+    label: String,
+    lease: Lease,
+    redis_conf: RedisConf,
+    start: Starter,
+    tx: Sender<SchedulerEvent>,
+    health: Health,
+}
+
+impl Election {
+    pub fn new(
+        conf: &LeaderElectionConf,
+        name: &str,
+        label: String,
+        redis_conf: RedisConf,
+        start: Starter,
+        tx: Sender<SchedulerEvent>,
+        health: Health,
+    ) -> Self {
+        let lease = Lease {
+            key: format!("{}{}", &conf.key_prefix, name),
+            instance: conf.instance(),
+            duration: conf.lease_duration(),
+        };
+        Self { label, lease, redis_conf, start, tx, health }
+    }
+
+    /// compete for the lease until `stop` is set, running the
+    /// threads while it's held, then release it
+    pub fn run(&self, stop: &AtomicBool) {
+        info!("election of the leader of {} launched", &self.label);
+        let mut con: Option<RedisConnection> = None;
+        let mut leading: Option<Leading> = None;
+        let mut last_renewal = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            if con.is_none() {
+                match self.redis_conf.open_connection() {
+                    Ok(c) => {
+                        con = Some(c);
+                    }
+                    Err(e) => {
+                        warn!("leader election can't connect to redis: {}", e);
+                    }
+                }
+            }
+            let held = match con.as_mut().map(|c| self.lease.renew_or_acquire(c)) {
+                Some(Ok(held)) => Some(held),
+                Some(Err(e)) => {
+                    warn!("lease {:?} can't be renewed: {}", &self.lease.key, e);
+                    con = None;
+                    None
+                }
+                None => None,
+            };
+            let leader = match held {
+                Some(held) => {
+                    if held {
+                        last_renewal = Instant::now();
+                    }
+                    held
+                }
+                // without answer from redis, the lease is assumed held until
+                // half its duration, to leave time to stop before it expires
+                None => leading.is_some() && last_renewal.elapsed() < self.lease.duration / 2,
+            };
+            if leader && leading.is_none() {
+                info!("leading {}", &self.label);
+                match self.start_leading() {
+                    Ok(started) => {
+                        leading = Some(started);
+                    }
+                    Err(e) => {
+                        error!("start of {} failed: {}", &self.label, e);
+                        let _ = self.tx.send(SchedulerEvent::WatcherFailed);
+                        break;
+                    }
+                }
+            } else if !leader {
+                if let Some(led) = leading.take() {
+                    info!("not leading {} anymore", &self.label);
+                    self.stop_leading(led);
+                }
+            }
+            sleep_unless_stopped(self.lease.duration / 3, stop);
+        }
+        if let Some(led) = leading.take() {
+            self.stop_leading(led);
+        }
+        // so that another instance takes over without waiting for the expiration
+        if let Some(c) = con.as_mut() {
+            if let Err(e) = self.lease.release(c) {
+                warn!("lease {:?} can't be released: {}", &self.lease.key, e);
+            }
+        }
+        info!("election of the leader of {} stopped", &self.label);
+    }
+
+    fn start_leading(&self) -> Result<Leading, RescError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handles = Vec::new();
+        match (self.start)(&stop, &mut handles) {
+            Ok(health_states) => {
+                self.health.add_watchers(health_states.clone());
+                Ok(Leading { stop, handles, health_states })
+            }
+            Err(e) => {
+                self.stop_leading(Leading { stop, handles, health_states: Vec::new() });
+                Err(e)
+            }
+        }
+    }
+
+    /// ask the threads to stop after their current
+    /// task, and wait for them
+    fn stop_leading(&self, leading: Leading) {
+        leading.stop.store(true, Ordering::Relaxed);
+        for h in leading.handles {
+            if h.join().is_err() {
+                warn!("a thread panicked");
+            }
+        }
+        self.health.remove_watchers(&leading.health_states);
+    }
+}
@@ -0,0 +1,130 @@
+//! Resc is a task orchestrator for distributed systems
+//! It's based on Rust and ensures in a safe way the
+//! generation of deduced tasks and their availability
+//! for external workers
+//!
+//! Introduction and complete description in the [README](https://github.com/Canop/resc)
+//!
+//! This library holds the whole engine: the configuration, the rules, the
+//! fetchers, and the watchers applying them, the `resc` binary being only
+//! the command line on top of it.
+
+mod audit;
+mod backoff;
+mod builtins;
+mod check;
+mod command_fetcher;
+mod condition;
+mod conf;
+mod control;
+mod delay;
+mod discovery;
+mod env_vars;
+mod errors;
+mod explain;
+mod failure_policy;
+mod fetch_auth;
+mod fetcher;
+mod fetcher_mock;
+mod fetcher_registry;
+mod generator;
+mod health;
+mod http_fetcher;
+mod inject;
+mod input;
+mod janitor;
+mod join;
+mod json_template;
+mod leader;
+mod lifecycle;
+mod make;
+mod metrics;
+mod pagination;
+mod pattern;
+mod queues;
+mod rate_limit;
+mod redis_conf;
+mod redis_connection;
+mod redis_fetcher;
+mod regex_list;
+mod replay;
+mod rule;
+mod ruleset;
+mod rule_result;
+mod rule_template;
+mod rule_test;
+mod scheduler;
+mod serde_format;
+mod shard;
+mod side_effect;
+mod simulate;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod sql_fetcher;
+mod task_format;
+mod telemetry;
+mod time_window;
+mod transform;
+mod wasm_plugin;
+mod watcher;
+
+pub use {
+    audit::*,
+    backoff::*,
+    builtins::*,
+    check::*,
+    command_fetcher::*,
+    condition::*,
+    conf::*,
+    control::*,
+    delay::*,
+    discovery::*,
+    env_vars::*,
+    errors::*,
+    explain::*,
+    failure_policy::*,
+    fetch_auth::*,
+    fetcher::*,
+    fetcher_mock::*,
+    fetcher_registry::*,
+    generator::*,
+    health::*,
+    http_fetcher::*,
+    inject::*,
+    input::*,
+    janitor::*,
+    join::*,
+    json_template::*,
+    leader::*,
+    lifecycle::*,
+    make::*,
+    metrics::*,
+    pagination::*,
+    pattern::*,
+    queues::*,
+    rate_limit::*,
+    redis_conf::*,
+    redis_connection::*,
+    redis_fetcher::*,
+    regex_list::*,
+    replay::*,
+    rule::*,
+    ruleset::*,
+    rule_result::*,
+    rule_template::*,
+    rule_test::*,
+    scheduler::*,
+    serde_format::*,
+    shard::*,
+    side_effect::*,
+    simulate::*,
+    task_format::*,
+    telemetry::*,
+    time_window::*,
+    transform::*,
+    wasm_plugin::*,
+    watcher::*,
+};
+
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use sql_fetcher::*;
+
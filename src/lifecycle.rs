@@ -0,0 +1,71 @@
+use {
+    crate::*,
+    redis::Commands,
+    serde::Serialize,
+};
+
+/// A step of the handling of a task, published as JSON
+/// on the events channel
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent<'a> {
+    /// a task was taken from the input of the watcher
+    TaskTaken {
+        task: &'a str,
+    },
+    /// a rule matched the task
+    RuleMatched {
+        task: &'a str,
+        rule: &'a str,
+    },
+    /// a task was produced by a rule, and written
+    /// or delayed
+    TaskCreated {
+        task: &'a str,
+        rule: &'a str,
+        created: &'a str,
+        target: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        delay: Option<f64>,
+    },
+    /// a task was handled without producing anything
+    TaskDropped {
+        task: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rule: Option<&'a str>,
+        reason: &'a str,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct LifecycleMessage<'a> {
+    time: f64,
+    watcher: &'a str,
+    #[serde(flatten)]
+    event: LifecycleEvent<'a>,
+}
+
+/// The publisher of the lifecycle events of a watcher
+#[derive(Debug, Clone)]
+pub struct LifecycleEvents {
+    pub channel: String,
+    /// the input of the watcher
+    pub watcher: String,
+}
+
+impl LifecycleEvents {
+    pub fn publish(
+        &self,
+        con: &mut RedisConnection,
+        event: LifecycleEvent<'_>,
+    ) -> Result<(), RescError> {
+        let message = LifecycleMessage {
+            time: now_secs(),
+            watcher: &self.watcher,
+            event,
+        };
+        let json = serde_json::to_string(&message).unwrap(); // only strings and numbers, can't fail
+        let _: () = con.publish(&self.channel, json)?;
+        Ok(())
+    }
+}
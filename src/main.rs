@@ -1,38 +1,14 @@
-//! Resc is a task orchestrator for distributed systems
-//! It's based on Rust and ensures in a safe way the
-//! generation of deduced tasks and their availability
-//! for external workers
-//!
-//! Introduction and complete description in the [README](https://github.com/Canop/resc)
+//! The resc command line: the scheduler, and the
+//! commands checking or exercising a configuration
 
-mod conf;
-mod errors;
-mod fetcher;
-mod make;
-mod pattern;
-mod rule;
-mod ruleset;
-mod rule_result;
-mod serde_format;
-mod watcher;
+mod cli;
 
 use {
     chrono::Local,
+    cli::*,
     log::*,
-    std::{env, io::Write, thread},
-};
-
-pub use {
-    conf::*,
-    errors::*,
-    fetcher::*,
-    make::*,
-    pattern::*,
-    rule::*,
-    ruleset::*,
-    rule_result::*,
-    serde_format::*,
-    watcher::*,
+    resc::*,
+    std::{io::Write, path::Path, process, sync::Arc},
 };
 
 fn configure_logger() {
@@ -55,34 +31,141 @@ fn configure_logger() {
 fn main() {
     configure_logger();
 
-    info!("----- starting resc scheduler -----");
-
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("no configuration file provided");
-    }
-    let config_filename = &args[1];
-    info!("configuration read from {}", config_filename);
-    let conf = match conf::read_file(config_filename) {
+    let args = match Args::from_env() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    info!("configuration read from {}", &args.conf_path);
+    let mut conf = match read_file(&args.conf_path) {
         Ok(conf) => conf,
         Err(e) => {
             error!("Error reading configuration: {}", &e);
             eprintln!("{}", e);
-            return;
+            process::exit(1);
         }
     };
 
-    let mut handles = Vec::new();
-    for watcher_conf in &conf.watchers {
-        let mut watcher = Watcher::new(watcher_conf, &conf).unwrap();
-        handles.push(thread::spawn(move || {
-            watcher.run().unwrap();
-        }));
+    let mocks = match args.mocks_path.as_deref().map(FetcherMocks::read_file).transpose() {
+        Ok(mocks) => mocks.map(Arc::new),
+        Err(e) => {
+            eprintln!("Invalid mock file: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(mocks) = &mocks {
+        conf.set_fetcher_mocks(mocks);
     }
 
-    debug!("all watchers started");
-
-    for h in handles {
-        h.join().unwrap();
+    match args.command {
+        Command::Check => {
+            let problems = check_conf(&conf);
+            for problem in &problems {
+                eprintln!("{}", problem);
+            }
+            if !problems.is_empty() {
+                process::exit(1);
+            }
+            println!("configuration OK");
+        }
+        Command::Test => {
+            let (count, failures) = run_tests(&conf);
+            for failure in &failures {
+                eprintln!("{}", failure);
+            }
+            println!("{} test(s), {} failure(s)", count, failures.len());
+            if !failures.is_empty() {
+                process::exit(1);
+            }
+        }
+        Command::Explain => {
+            let fetch_results = match read_fetch_results(args.fetch_results_path.as_deref()) {
+                Ok(fetch_results) => fetch_results,
+                Err(e) => {
+                    eprintln!("Invalid fetch results: {}", e);
+                    process::exit(1);
+                }
+            };
+            let task = args.task.unwrap_or_default(); // checked on parsing
+            for line in explain(&conf, &task, &fetch_results) {
+                println!("{}", line);
+            }
+        }
+        Command::Simulate => {
+            let tasks_path = args.tasks_path.unwrap_or_default(); // checked on parsing
+            let tasks = match read_tasks(Path::new(&tasks_path)) {
+                Ok(tasks) => tasks,
+                Err(e) => {
+                    eprintln!("Error reading the tasks: {}", e);
+                    process::exit(1);
+                }
+            };
+            let report = simulate(&conf, tasks);
+            match &args.report_path {
+                Some(report_path) => {
+                    if let Err(e) = report.write_file(Path::new(report_path)) {
+                        eprintln!("Error writing the report: {}", e);
+                        process::exit(1);
+                    }
+                    report.print_totals();
+                }
+                None => report.print(),
+            }
+            if report.failures > 0 {
+                process::exit(1);
+            }
+        }
+        Command::Inject => {
+            let task = args.task.unwrap_or_default(); // checked on parsing
+            match inject(&conf, args.queue.as_deref(), &task) {
+                Ok(queue) => println!("{:?} pushed to queue {:?}", task, queue),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Queues => {
+            if let Err(e) = print_queues(&conf) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        Command::Replay => {
+            let replay_args = Replay {
+                from: args.from.as_deref().unwrap_or_default(), // checked on parsing
+                queue: args.queue.as_deref(),
+                limit: args.limit,
+                filter: args.filter.as_ref(),
+                rate: args.rate.unwrap_or(DEFAULT_REPLAY_RATE),
+            };
+            match replay(&conf, &replay_args) {
+                Ok((queue, count)) => println!("{} task(s) replayed in queue {:?}", count, queue),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Run => {
+            info!("----- starting resc scheduler -----");
+            conf.dry_run |= args.dry_run;
+            conf.strict |= args.strict;
+            let problems = check_conf(&conf);
+            for problem in &problems {
+                warn!("{}", problem);
+            }
+            if conf.strict && !problems.is_empty() {
+                eprintln!("{} problem(s) in the configuration, not starting in strict mode", problems.len());
+                process::exit(1);
+            }
+            if let Err(e) = Scheduler::new(args.conf_path, conf, args.dry_run, mocks).run() {
+                error!("Scheduler stopped: {}", &e);
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
     }
 }
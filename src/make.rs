@@ -1,72 +1,390 @@
 use {
     crate::*,
-    serde::Deserialize,
-    std::collections::HashMap,
+    log::*,
+    serde::{
+        de::{
+            self,
+            value::{MapAccessDeserializer, SeqAccessDeserializer},
+            MapAccess,
+            SeqAccess,
+            Visitor,
+        },
+        Deserialize,
+        Deserializer,
+    },
+    std::{
+        collections::HashMap,
+        fmt,
+    },
 };
 
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Maker {
 
     /// an optional name, for logs and for documentation in formats
     /// not allowing comments
     pub name: Option<String>,
 
+    /// an optional condition on the properties, the element
+    /// making no task when they don't verify it
+    pub when: Option<Condition>,
+
     /// the output task generation pattern, defined with token
-    /// found with on_regex or a fetcher
-    #[serde(default = "Pattern::default_task")]
-    pub task: Pattern,
+    /// found with on_regex or a fetcher, or a JSON template
+    #[serde(default = "TaskTemplate::default_task")]
+    pub task: TaskTemplate,
 
     /// the queue where the generated tasks must be written
-    pub queue: Pattern,
+    pub queue: Option<Pattern>,
+
+    /// the stream where the generated tasks must be written,
+    /// as an alternative to the queue
+    pub stream: Option<Pattern>,
+
+    /// the sorted set where the generated tasks must be added,
+    /// as an alternative to the queue
+    pub zset: Option<Pattern>,
+
+    /// the score of the task in the sorted set, like
+    /// a priority or a timestamp
+    pub score: Option<Pattern>,
+
+    /// the end of the queue where the tasks are pushed
+    /// (default: left)
+    pub push: Option<PushSide>,
 
     /// the optional task set used for deduplicating
     pub set: Option<Pattern>,
 
+    /// the duration, in seconds, after which a task of
+    /// the set is forgotten
+    pub set_ttl: Option<u64>,
+
+    /// the optional key used for deduplicating, as an alternative
+    /// to the set: the task isn't written when the key exists
+    pub dedup_key: Option<Pattern>,
+
+    /// the duration, in seconds, after which the dedup key expires
+    pub dedup_ttl: Option<u64>,
+
+    /// an optional delay, in seconds, before the task is
+    /// written to its queue or stream
+    pub delay: Option<Pattern>,
+
+    /// the maximal rate of the tasks written to the queue or
+    /// stream, like "10/s", beyond which they're delayed
+    pub rate_limit: Option<RateLimit>,
+
+    /// the length of the queue beyond which the tasks aren't
+    /// pushed but parked in the delayed set until it shrinks
+    pub max_queue_len: Option<usize>,
+
+    /// when set, the task is written only once it has been
+    /// produced by all the expected parents
+    pub join: Option<JoinConf>,
+
+    /// the name of the Redis server, declared in `redis_servers`,
+    /// where the task must be written, when it's not the one of
+    /// the watcher
+    pub redis: Option<String>,
+
+    /// auxiliary writes, like the increment of a counter,
+    /// done when the task is written
+    #[serde(default)]
+    pub effects: Vec<SideEffect>,
+
+    /// a WebAssembly module whose `transform` function changes
+    /// the task, or drops it by returning nothing
+    pub wasm_transform: Option<WasmPlugin>,
+
 }
 impl Maker {
+    /// check the consistency of the maker, beyond
+    /// what serde derive checks
+    fn validate(&self) -> Result<(), &'static str> {
+        let targets = [&self.queue, &self.stream, &self.zset].iter()
+            .filter(|target| target.is_some())
+            .count();
+        if targets != 1 {
+            return Err("a make element needs either a queue, a stream, or a zset");
+        }
+        if self.queue.is_none() && self.push.is_some() {
+            return Err("push is only for queues");
+        }
+        if self.queue.is_none() && self.max_queue_len.is_some() {
+            return Err("max_queue_len is only for queues");
+        }
+        if self.zset.is_some() != self.score.is_some() {
+            return Err("a zset needs a score, and a score is only for zsets");
+        }
+        if self.set.is_some() && self.dedup_key.is_some() {
+            return Err("a make element can't have both a set and a dedup_key");
+        }
+        if self.set_ttl.is_some() && self.set.is_none() {
+            return Err("set_ttl needs a set");
+        }
+        if self.dedup_ttl.is_some() && self.dedup_key.is_none() {
+            return Err("dedup_ttl needs a dedup_key");
+        }
+        for effect in &self.effects {
+            effect.validate()?;
+        }
+        Ok(())
+    }
+    fn dedup(
+        &self,
+        inject: impl Fn(&Pattern) -> Result<String, RescError>,
+    ) -> Result<Option<Dedup>, RescError> {
+        if let Some(set) = &self.set {
+            return Ok(Some(Dedup::Set {
+                set: inject(set)?,
+                ttl: self.set_ttl,
+            }));
+        }
+        self.dedup_key.as_ref()
+            .map(|key| Ok(Dedup::Key {
+                key: inject(key)?,
+                ttl: self.dedup_ttl,
+            }))
+            .transpose()
+    }
+    /// make the task. When `strict` is true, a variable without
+    /// value in a pattern makes it fail.
+    ///
+    /// The built-in variables are valued once per task, so that
+    /// for example `${uuid}` is the same in all its patterns
     pub fn make(
         &self,
         props: &HashMap<String, String>,
+        strict: bool,
         results: &mut Vec<RuleResult>,
-    ) {
+    ) -> Result<(), RescError> {
+        if let Some(when) = &self.when {
+            if !when.eval(props) {
+                debug!("    make element skipped by {:?}", when.src);
+                return Ok(());
+            }
+        }
+        let props_with_builtins = with_builtins(props);
+        let inject = |pattern: &Pattern| {
+            if strict {
+                pattern.try_inject(&props_with_builtins)
+            } else {
+                Ok(pattern.inject(&props_with_builtins))
+            }
+        };
+        let task = match &self.task {
+            TaskTemplate::Pattern(pattern) => inject(pattern)?,
+            TaskTemplate::Json(template) => {
+                if strict {
+                    for pattern in template.patterns() {
+                        pattern.try_inject(&props_with_builtins)?;
+                    }
+                }
+                template.inject(&props_with_builtins).to_string()
+            }
+        };
+        let task = match &self.wasm_transform {
+            Some(plugin) => match plugin.transform(task)? {
+                Some(task) => task,
+                None => {
+                    debug!("    task dropped by {:?}", &plugin.path);
+                    return Ok(());
+                }
+            },
+            None => task,
+        };
+        let target = match (&self.queue, &self.stream, &self.zset) {
+            (Some(queue), _, _) => TaskTarget::Queue(inject(queue)?),
+            (None, Some(stream), _) => {
+                let mut fields = vec![("task".to_string(), task.clone())];
+                for (key, value) in props {
+                    fields.push((key.clone(), value.clone()));
+                }
+                TaskTarget::Stream {
+                    stream: inject(stream)?,
+                    fields,
+                }
+            }
+            (None, None, Some(zset)) => {
+                let score = inject(self.score.as_ref().unwrap())?; // checked on deserialization
+                match score.trim().parse::<f64>() {
+                    Ok(score) if score.is_finite() => TaskTarget::Zset {
+                        zset: inject(zset)?,
+                        score,
+                    },
+                    _ => return Err(RescError::InvalidScore(score)),
+                }
+            }
+            (None, None, None) => {
+                // checked on deserialization, but a Maker may be built otherwise
+                return Err(ConfError::MissingMakeTarget.into());
+            }
+        };
+        let delay = match &self.delay {
+            Some(pattern) => {
+                let delay = inject(pattern)?;
+                match delay.trim().parse::<f64>() {
+                    Ok(secs) if secs >= 0.0 => Some(secs),
+                    _ => return Err(RescError::InvalidDelay(delay)),
+                }
+            }
+            None => None,
+        };
+        let effects = self.effects.iter()
+            .map(|effect| effect.effect(inject))
+            .collect::<Result<Vec<Effect>, RescError>>()?;
+        let join = self.join.as_ref()
+            .map(|join| join.join(inject))
+            .transpose()?;
         results.push(RuleResult {
-            task: self.task.inject(props),
-            queue: self.queue.inject(props),
-            set: self.set.as_ref().map(|pattern| pattern.inject(props)),
+            task,
+            target,
+            push: self.push.unwrap_or_default(),
+            dedup: self.dedup(inject)?,
+            redis: self.redis.clone(),
+            delay,
+            rate_limit: self.rate_limit,
+            max_queue_len: self.max_queue_len,
+            effects,
+            join,
         });
+        Ok(())
+    }
+}
+
+/// What a make element builds the task from
+#[derive(Debug, Clone)]
+pub enum TaskTemplate {
+
+    /// a string pattern
+    Pattern(Pattern),
+
+    /// a JSON object or array whose strings are patterns,
+    /// the task being its serialization
+    Json(JsonTemplate),
+
+}
+
+impl TaskTemplate {
+    /// produce the template to use when the config gives none
+    pub fn default_task() -> Self {
+        Self::Pattern(Pattern::default_task())
+    }
+    /// the patterns of the template
+    pub fn patterns(&self) -> Vec<Pattern> {
+        match self {
+            Self::Pattern(pattern) => vec![pattern.clone()],
+            Self::Json(template) => template.patterns(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct TaskTemplateVisitor;
+        impl<'de> Visitor<'de> for TaskTemplateVisitor {
+            type Value = TaskTemplate;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a pattern or a JSON object or array")
+            }
+            fn visit_str<E>(self, s: &str) -> Result<TaskTemplate, E>
+                where E: de::Error
+            {
+                Pattern::new(s)
+                    .map(TaskTemplate::Pattern)
+                    .map_err(de::Error::custom)
+            }
+            fn visit_map<A>(self, map: A) -> Result<TaskTemplate, A::Error>
+                where A: MapAccess<'de>
+            {
+                let template = JsonTemplate::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(TaskTemplate::Json(template))
+            }
+            fn visit_seq<A>(self, seq: A) -> Result<TaskTemplate, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let template = JsonTemplate::deserialize(SeqAccessDeserializer::new(seq))?;
+                Ok(TaskTemplate::Json(template))
+            }
+        }
+        deserializer.deserialize_any(TaskTemplateVisitor)
     }
 }
 
 /// This mimics the configuration structure where Make
 /// elements can be given in an array or just single.
-/// For now there's no difference and a single works
-/// just as a 1 element array.
-#[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
+/// A single element works just as a 1 element array.
+///
+/// It's not deserialized as an untagged enum because the
+/// errors would then be reported in a too vague way.
+#[derive(Debug, Clone)]
 pub enum Makers {
 
-    Single(Maker),
+    Single(Box<Maker>),
 
     Multiple(Vec<Maker>),
 
 }
 
 impl Makers {
+    pub fn as_slice(&self) -> &[Maker] {
+        match self {
+            Self::Single(maker) => std::slice::from_ref(maker.as_ref()),
+            Self::Multiple(vec) => vec,
+        }
+    }
     pub fn make(
         &self,
         props: &HashMap<String, String>,
+        strict: bool,
         results: &mut Vec<RuleResult>,
-    ) {
+    ) -> Result<(), RescError> {
         match self {
             Self::Single(maker) => {
-                maker.make(props, results);
+                maker.make(props, strict, results)?;
             }
             Self::Multiple(vec) => {
                 for maker in vec {
-                    maker.make(props, results);
+                    maker.make(props, strict, results)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Makers {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct MakersVisitor;
+        impl<'de> Visitor<'de> for MakersVisitor {
+            type Value = Makers;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a make object or an array of make objects")
+            }
+            fn visit_map<A>(self, map: A) -> Result<Makers, A::Error>
+                where A: MapAccess<'de>
+            {
+                let maker = Maker::deserialize(MapAccessDeserializer::new(map))?;
+                maker.validate().map_err(de::Error::custom)?;
+                Ok(Makers::Single(Box::new(maker)))
+            }
+            fn visit_seq<A>(self, seq: A) -> Result<Makers, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let makers = Vec::<Maker>::deserialize(SeqAccessDeserializer::new(seq))?;
+                for maker in &makers {
+                    maker.validate().map_err(de::Error::custom)?;
                 }
+                Ok(Makers::Multiple(makers))
             }
         }
+        deserializer.deserialize_any(MakersVisitor)
     }
 }
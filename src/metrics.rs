@@ -0,0 +1,99 @@
+use {
+    crate::*,
+    log::*,
+    serde::Deserialize,
+    std::{
+        net::UdpSocket,
+        time::Duration,
+    },
+};
+
+/// Configuration of the emission of metrics to a StatsD server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConf {
+
+    /// the address of the StatsD server, e.g. "127.0.0.1:8125"
+    pub statsd: String,
+
+    /// the prefix of the names of the metrics
+    #[serde(default = "MetricsConf::default_prefix")]
+    pub prefix: String,
+
+    /// whether tags are sent the DogStatsD way instead of
+    /// being appended to the names of the metrics
+    #[serde(default)]
+    pub dogstatsd: bool,
+
+}
+
+impl MetricsConf {
+    pub fn default_prefix() -> String {
+        "resc".to_string()
+    }
+}
+
+/// A client sending metrics, over UDP, to a StatsD server.
+///
+/// Sending errors are ignored, as metrics must never
+/// prevent tasks from being handled.
+#[derive(Debug)]
+pub struct Metrics {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+}
+
+impl Metrics {
+    pub fn new(conf: &MetricsConf) -> Result<Self, RescError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&conf.statsd)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: conf.prefix.clone(),
+            dogstatsd: conf.dogstatsd,
+        })
+    }
+
+    /// increment a counter
+    pub fn count(&self, name: &str, value: usize, tags: &[(&str, &str)]) {
+        self.send(name, &format!("{}|c", value), tags);
+    }
+
+    /// record a duration, in milliseconds
+    pub fn timing(&self, name: &str, duration: Duration, tags: &[(&str, &str)]) {
+        self.send(name, &format!("{}|ms", duration.as_millis()), tags);
+    }
+
+    fn send(&self, name: &str, value: &str, tags: &[(&str, &str)]) {
+        let line = if self.dogstatsd {
+            let mut line = format!("{}.{}:{}", self.prefix, name, value);
+            for (i, (key, tag_value)) in tags.iter().enumerate() {
+                line.push(if i == 0 { '|' } else { ',' });
+                if i == 0 {
+                    line.push('#');
+                }
+                line.push_str(&format!("{}:{}", key, sanitize(tag_value)));
+            }
+            line
+        } else {
+            let mut full_name = format!("{}.{}", self.prefix, name);
+            for (_, tag_value) in tags {
+                full_name.push('.');
+                full_name.push_str(&sanitize(tag_value));
+            }
+            format!("{}:{}", full_name, value)
+        };
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!("metric {:?} not sent: {}", line, e);
+        }
+    }
+}
+
+/// make a value usable in a metric name or tag
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
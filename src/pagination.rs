@@ -0,0 +1,98 @@
+use {
+    crate::*,
+    jsonpath_lib::Compiled,
+    serde::Deserialize,
+    serde_json::Value,
+};
+
+/// How a fetcher finds the next page of a paginated response
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pagination {
+
+    /// a JSONPath expression selecting, in the response,
+    /// the url of the next page, e.g. "$.next"
+    #[serde(default, deserialize_with = "deserialize_json_path")]
+    pub next: Option<Compiled>,
+
+    /// whether the url of the next page is given by
+    /// the `Link` header, with `rel="next"`
+    #[serde(default)]
+    pub link_header: bool,
+
+    /// the maximal number of pages queried
+    #[serde(default = "Pagination::default_max_pages")]
+    pub max_pages: usize,
+
+}
+
+impl Pagination {
+    pub fn default_max_pages() -> usize {
+        10
+    }
+
+    /// the url of the next page, if any, which may be
+    /// relative to the url of the current one
+    pub fn next_url(
+        &self,
+        url: &str,
+        value: &Value,
+        link_header: Option<&str>,
+    ) -> Result<Option<String>, FetchError> {
+        let mut next = None;
+        if let Some(path) = &self.next {
+            let selected = path.select(value)
+                .map_err(|e| FetchError::Extract(format!("{:?}", e)))?;
+            next = selected.first()
+                .and_then(|value| value.as_str())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string);
+        }
+        if next.is_none() && self.link_header {
+            next = link_header.and_then(next_link);
+        }
+        match next {
+            Some(next) => {
+                let next = reqwest::Url::parse(url)
+                    .and_then(|url| url.join(&next))
+                    .map_err(|_| FetchError::InvalidNextPage(next))?;
+                Ok(Some(next.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// find the url with `rel="next"` in the value of a `Link` header,
+/// which looks like `<https://a/?page=3>; rel="next", <https://a/?page=1>; rel="prev"`
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        parts
+            .map(|param| param.trim().replace(' ', ""))
+            .any(|param| param == "rel=\"next\"" || param == "rel=next")
+            .then(|| url.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_link() {
+        assert_eq!(
+            next_link(r#"<https://a/?page=3>; rel="next", <https://a/?page=1>; rel="prev""#),
+            Some("https://a/?page=3".to_string()),
+        );
+        assert_eq!(
+            next_link(r#"<https://a/?page=1>; rel="prev", </items?page=3>; rel = "next""#),
+            Some("/items?page=3".to_string()),
+        );
+        assert_eq!(next_link("<https://a/?page=2>; rel=next"), Some("https://a/?page=2".to_string()));
+        assert_eq!(next_link(r#"<https://a/?page=1>; rel="prev""#), None);
+        assert_eq!(next_link(r#"https://a/?page=2; rel="next""#), None);
+        assert_eq!(next_link(""), None);
+    }
+}
@@ -1,35 +1,289 @@
 use {
+    crate::*,
+    chrono::{
+        format::{Item, StrftimeItems},
+        DateTime,
+        Duration,
+        TimeZone,
+        Utc,
+    },
     lazy_static::lazy_static,
     regex::{Captures, Regex},
-    serde::{Deserialize, Deserializer},
-    std::collections::HashMap,
+    serde::{de, Deserialize, Deserializer},
+    std::{collections::HashMap, str::FromStr},
 };
 
+lazy_static! {
+    static ref OUT_GROUP_REGEX: Regex = Regex::new(
+        r"\$\{([\w.]+)([+-]\d+[smhdw])?(?::-([^|}]*))?((?:\|[^|}]+)*)\}"
+    ).unwrap();
+}
+
 /// Patterns are built from strings like "bla ${some_var} ${some.otherone} bla"
-/// and are expanded with HashMap<String, String>
+/// and are expanded with HashMap<String, String>.
+///
+/// A variable may be followed by a time offset, like in `${now-1d}`,
+/// then by a default value, used when the property is missing, like
+/// in `${region:-eu}`, then by filters transforming its value, like
+/// in `${name|trim|lower}` or `${now|date:%Y-%m-%d}`.
 /// TODO use an enum, and define an identity for the simple case
 #[derive(Debug, Clone)]
 pub struct Pattern {
     pub src: String,
+    /// the filters of each variable, in order, parsed once
+    filters: Vec<Vec<PatternFilter>>,
+}
+
+/// A transformation of the value of a variable, on injection
+#[derive(Debug, Clone)]
+pub enum PatternFilter {
+    Lower,
+    Upper,
+    /// percent-encode all but the unreserved characters of RFC 3986
+    UrlEncode,
+    Trim,
+    /// format a time, given as a Unix timestamp or in RFC 3339,
+    /// in UTC with a strftime like format
+    Date(String),
+    /// replace all the matches of a regex, the replacement
+    /// possibly referring to the groups, like `$1`
+    Replace(Regex, String),
+    /// a stable hash of the value (64 bits FNV-1a),
+    /// modulo the given number
+    Hash(u64),
 }
 
 impl Pattern {
+    /// build a pattern, parsing its filters and checking
+    /// its time offsets are small enough to be applied
+    pub fn new(src: &str) -> Result<Self, ConfError> {
+        let invalid = |reason| ConfError::InvalidPattern {
+            pattern: src.to_string(),
+            reason,
+        };
+        let mut filters = Vec::new();
+        for caps in OUT_GROUP_REGEX.captures_iter(src) {
+            if let Some(offset) = caps.get(2) {
+                if parse_offset(offset.as_str()).is_none() {
+                    return Err(invalid(format!("time offset {:?} out of range", offset.as_str())));
+                }
+            }
+            let group_filters = filter_names(&caps)
+                .map(str::parse::<PatternFilter>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+            filters.push(group_filters);
+        }
+        Ok(Self {
+            src: src.to_string(),
+            filters,
+        })
+    }
     pub fn inject(&self, props: &HashMap<String, String>) -> String {
-        lazy_static! {
-            static ref OUT_GROUP_REGEX: Regex = Regex::new(r"\$\{([\w.]+)\}").unwrap();
+        self.expand(props).0
+    }
+    /// inject the properties, failing when a variable
+    /// has no value and no default value
+    pub fn try_inject(&self, props: &HashMap<String, String>) -> Result<String, RescError> {
+        match self.expand(props) {
+            (injected, None) => Ok(injected),
+            (_, Some(variable)) => Err(RescError::MissingVariable {
+                variable,
+                pattern: self.src.clone(),
+            }),
         }
-        OUT_GROUP_REGEX
+    }
+    /// inject the properties, and return the result with
+    /// the first variable which couldn't be valued, if any
+    fn expand(&self, props: &HashMap<String, String>) -> (String, Option<String>) {
+        let mut missing = None;
+        let mut filters = self.filters.iter();
+        let injected = OUT_GROUP_REGEX
             .replace_all(&self.src, |caps: &Captures| {
-                match props.get(caps.get(1).unwrap().as_str()) {
-                    Some(value) => value,
-                    None => "-missing group!-", // we'll probably panic later on
+                let filters = filters.next().unwrap(); // parsed for each variable in new
+                let value = props.get(caps.get(1).unwrap().as_str())
+                    .map(|value| match caps.get(2).and_then(|offset| parse_offset(offset.as_str())) {
+                        Some(offset) => shift_time(value, offset),
+                        None => value.to_string(),
+                    })
+                    .or_else(|| caps.get(3).map(|default| default.as_str().to_string()));
+                match value {
+                    Some(value) => filters.iter()
+                        .fold(value, |value, filter| filter.apply(&value)),
+                    None => {
+                        missing.get_or_insert_with(|| caps.get(1).unwrap().as_str().to_string());
+                        "-missing group!-".to_string()
+                    }
                 }
             })
-            .to_string()
+            .to_string();
+        (injected, missing)
+    }
+    /// the names of the variables used in the pattern
+    /// without a default value
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        OUT_GROUP_REGEX
+            .captures_iter(&self.src)
+            .filter(|caps| caps.get(3).is_none())
+            .map(|caps| caps.get(1).unwrap().as_str())
+    }
+    /// whether the pattern contains variables
+    pub fn is_constant(&self) -> bool {
+        !OUT_GROUP_REGEX.is_match(&self.src)
+    }
+    /// a Redis glob matching all the values of the pattern,
+    /// the variables being replaced with `*`
+    pub fn glob(&self) -> String {
+        OUT_GROUP_REGEX.replace_all(&self.src, "*").to_string()
     }
     /// produce the pattern to use when the config gives none
     pub fn default_task() -> Self {
-        Self { src: "${input_task}".to_owned() }
+        Self::new("${input_task}").unwrap() // has no filter, can't fail
+    }
+}
+
+/// the filters of a variable of a pattern, with their arguments
+fn filter_names<'c>(caps: &'c Captures) -> impl Iterator<Item = &'c str> {
+    caps.get(4).unwrap().as_str().split('|').skip(1).map(str::trim)
+}
+
+/// parse a time given as a Unix timestamp, in seconds,
+/// or in RFC 3339
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Utc.timestamp_opt(secs.floor() as i64, 0).single();
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// parse an offset like "-1d" or "+2h", returning None
+/// when it's too large to be a duration
+fn parse_offset(offset: &str) -> Option<Duration> {
+    let (number, unit) = offset.split_at(offset.len() - 1);
+    let number: i64 = number.parse().ok()?;
+    let secs = number.checked_mul(match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => 7 * 86400,
+    })?;
+    Duration::try_seconds(secs)
+}
+
+/// apply an offset to a time, keeping its format. A value which
+/// isn't a time, or would be shifted out of range, is returned unchanged
+fn shift_time(value: &str, offset: Duration) -> String {
+    let secs = offset.num_seconds();
+    if let Ok(timestamp) = value.trim().parse::<i64>() {
+        return timestamp.checked_add(secs)
+            .map_or_else(|| value.to_string(), |timestamp| timestamp.to_string());
+    }
+    if let Ok(timestamp) = value.trim().parse::<f64>() {
+        return (timestamp + secs as f64).to_string();
+    }
+    DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .and_then(|time| time.checked_add_signed(offset))
+        .map_or_else(|| value.to_string(), |time| time.to_rfc3339())
+}
+
+/// split the argument of the replace filter, like `/-/_/`, in
+/// its regex and its replacement. A slash in the regex or the
+/// replacement is escaped with a backslash
+fn parse_replace(arg: &str) -> Option<(String, String)> {
+    let inner = arg.strip_prefix('/')?.strip_suffix('/')?;
+    let mut parts = vec![String::new()];
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'/') => {
+                parts.last_mut().unwrap().push('/');
+                chars.next();
+            }
+            '/' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    if parts.len() != 2 {
+        return None;
+    }
+    let replacement = parts.pop()?;
+    Some((parts.pop()?, replacement))
+}
+
+/// the 64 bits FNV-1a hash, which doesn't change between
+/// versions and platforms, so that sharding is stable
+pub(crate) fn fnv1a(value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in value.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl PatternFilter {
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Self::Lower => value.to_lowercase(),
+            Self::Upper => value.to_uppercase(),
+            Self::UrlEncode => {
+                let mut encoded = String::new();
+                for b in value.bytes() {
+                    if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                        encoded.push(b as char);
+                    } else {
+                        encoded.push_str(&format!("%{:02X}", b));
+                    }
+                }
+                encoded
+            }
+            Self::Trim => value.trim().to_string(),
+            Self::Date(format) => match parse_time(value) {
+                Some(time) => time.format(format).to_string(),
+                None => value.to_string(),
+            },
+            Self::Replace(regex, replacement) => {
+                regex.replace_all(value, replacement.as_str()).to_string()
+            }
+            Self::Hash(modulo) => (fnv1a(value) % modulo).to_string(),
+        }
+    }
+}
+
+impl FromStr for PatternFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "lower" => Ok(Self::Lower),
+            None if s == "upper" => Ok(Self::Upper),
+            None if s == "urlencode" => Ok(Self::UrlEncode),
+            None if s == "trim" => Ok(Self::Trim),
+            Some(("date", format)) => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(format!("invalid date format {:?}", format));
+                }
+                Ok(Self::Date(format.to_string()))
+            }
+            Some(("replace", arg)) => {
+                let (regex, replacement) = parse_replace(arg)
+                    .ok_or_else(|| format!("invalid replace {:?}, expected /<regex>/<replacement>/", arg))?;
+                let regex = Regex::new(&regex).map_err(|e| e.to_string())?;
+                Ok(Self::Replace(regex, replacement))
+            }
+            Some(("hash", modulo)) => match modulo.parse() {
+                Ok(modulo) if modulo > 0 => Ok(Self::Hash(modulo)),
+                _ => Err(format!("invalid hash modulo {:?}", modulo)),
+            },
+            _ => Err(format!(
+                "unknown filter {:?}, expected lower, upper, urlencode, trim, date:<format>, replace:/<regex>/<replacement>/, or hash:<n>",
+                s,
+            )),
+        }
     }
 }
 
@@ -38,6 +292,83 @@ impl<'de> Deserialize<'de> for Pattern {
         where D: Deserializer<'de>
     {
         let src = String::deserialize(deserializer)?;
-        Ok(Self { src })
+        Self::new(&src).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("+30s"), Some(Duration::seconds(30)));
+        assert_eq!(parse_offset("-5m"), Some(Duration::minutes(-5)));
+        assert_eq!(parse_offset("+2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_offset("-1d"), Some(Duration::days(-1)));
+        assert_eq!(parse_offset("+3w"), Some(Duration::weeks(3)));
+        assert_eq!(parse_offset("+99999999999999999w"), None);
+        assert_eq!(parse_offset("+999999999999999999999s"), None);
+    }
+
+    #[test]
+    fn test_shift_time() {
+        assert_eq!(shift_time("1000", Duration::seconds(60)), "1060");
+        assert_eq!(shift_time(" 1000 ", Duration::hours(-1)), "-2600");
+        assert_eq!(shift_time("1000.5", Duration::seconds(60)), "1060.5");
+        assert_eq!(
+            shift_time("2024-03-01T12:00:00+00:00", Duration::days(-1)),
+            "2024-02-29T12:00:00+00:00",
+        );
+        assert_eq!(
+            shift_time("2024-03-01T12:00:00+02:00", Duration::hours(1)),
+            "2024-03-01T13:00:00+02:00",
+        );
+        assert_eq!(shift_time("not a time", Duration::days(1)), "not a time");
+        assert_eq!(shift_time(&i64::MAX.to_string(), Duration::seconds(1)), i64::MAX.to_string());
+    }
+
+    #[test]
+    fn test_date() {
+        let date: PatternFilter = "date:%Y-%m-%d %H:%M".parse().unwrap();
+        assert_eq!(date.apply("0"), "1970-01-01 00:00");
+        assert_eq!(date.apply("86399.9"), "1970-01-01 23:59");
+        assert_eq!(date.apply("2024-03-01T01:30:00+02:00"), "2024-02-29 23:30");
+        assert_eq!(date.apply("not a time"), "not a time");
+        assert!("date:%Y-%".parse::<PatternFilter>().is_err());
+    }
+
+    #[test]
+    fn test_parse_replace() {
+        assert_eq!(parse_replace("/-/_/"), Some(("-".to_string(), "_".to_string())));
+        assert_eq!(parse_replace(r"/(\d+)-(\d+)/$1$2/"), Some((r"(\d+)-(\d+)".to_string(), "$1$2".to_string())));
+        assert_eq!(parse_replace(r"/a\/b/c\/d/"), Some(("a/b".to_string(), "c/d".to_string())));
+        assert_eq!(parse_replace("/x//"), Some(("x".to_string(), String::new())));
+        assert_eq!(parse_replace("/x/y"), None);
+        assert_eq!(parse_replace("/x/"), None);
+        assert_eq!(parse_replace("/x/y/z/"), None);
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // the reference values of FNV-1a, which must never change
+        // as they decide the shards of the tasks
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a("foobar"), 0x85944171f73967e8);
+        let hash: PatternFilter = "hash:8".parse().unwrap();
+        assert_eq!(hash.apply("foobar"), (0x85944171f73967e8u64 % 8).to_string());
+        assert!("hash:0".parse::<PatternFilter>().is_err());
+    }
+
+    #[test]
+    fn test_shifted_date() {
+        let pattern = Pattern::new("day/${t-1d|date:%Y%m%d}").unwrap();
+        assert_eq!(pattern.inject(&props(&[("t", "172800")])), "day/19700102");
+        assert!(Pattern::new("${t+99999999999999999w}").is_err());
     }
 }
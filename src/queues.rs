@@ -0,0 +1,167 @@
+use {
+    crate::*,
+    redis::Commands,
+    std::collections::BTreeSet,
+};
+
+/// The role of a queue in the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QueueKind {
+    Input,
+    Taken,
+    Output,
+}
+
+impl QueueKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Taken => "taken",
+            Self::Output => "output",
+        }
+    }
+}
+
+/// A queue referenced by the configuration: its kind, its name,
+/// and whether the name is a glob, for the output queues whose
+/// names are patterns with variables
+type QueueRef = (QueueKind, String, bool);
+
+/// The queues referenced by the configuration on a Redis server
+struct ServerQueues<'c> {
+    label: String,
+    redis: &'c RedisConf,
+    queues: BTreeSet<QueueRef>,
+}
+
+/// get the queues of a server, adding it if it's not yet known
+fn server<'s, 'c>(
+    servers: &'s mut Vec<ServerQueues<'c>>,
+    label: String,
+    redis: &'c RedisConf,
+) -> &'s mut ServerQueues<'c> {
+    let idx = match servers.iter().position(|server| server.label == label) {
+        Some(idx) => idx,
+        None => {
+            servers.push(ServerQueues { label, redis, queues: BTreeSet::new() });
+            servers.len() - 1
+        }
+    };
+    &mut servers[idx]
+}
+
+/// list, by Redis server, the input and taken queues of the
+/// watchers, and the queues where the rules write
+fn referenced_queues(conf: &Conf) -> Result<Vec<ServerQueues<'_>>, RescError> {
+    let main_label = "main Redis server".to_string();
+    let mut servers = Vec::new();
+    server(&mut servers, main_label.clone(), &conf.redis);
+    for watcher in &conf.watchers {
+        let (label, redis) = match &watcher.redis {
+            Some(redis) => (format!("Redis server of the watcher on {}", watcher.label()?), redis),
+            None => (main_label.clone(), &conf.redis),
+        };
+        let mut queues = BTreeSet::new();
+        if let Some(pattern) = &watcher.input_queue_pattern {
+            queues.insert((QueueKind::Input, pattern.clone(), true));
+            queues.insert((QueueKind::Taken, format!("{}/taken*", pattern), true));
+        } else {
+            for slot in 0..watcher.concurrency() {
+                if let Input::Queue { queue, taken_queue, .. } = watcher.slot_input(slot)? {
+                    queues.insert((QueueKind::Input, queue, false));
+                    queues.insert((QueueKind::Taken, taken_queue, false));
+                }
+            }
+        }
+        if let Some(queue) = &watcher.unmatched_queue {
+            queues.insert((QueueKind::Output, queue.clone(), false));
+        }
+        for rule in &watcher.rules {
+            if let FailurePolicy::DeadLetter(queue) = &rule.on_failure {
+                queues.insert((QueueKind::Output, queue.clone(), false));
+            }
+            for maker in rule.makers.as_slice() {
+                let (queue, is_glob) = match &maker.queue {
+                    Some(queue) if queue.is_constant() => (queue.src.clone(), false),
+                    Some(queue) => (queue.glob(), true),
+                    None => continue,
+                };
+                match &maker.redis {
+                    Some(name) => {
+                        let redis = conf.redis_servers.get(name)
+                            .ok_or_else(|| ConfError::UnknownRedisServer(name.clone()))?;
+                        server(&mut servers, format!("Redis server {:?}", name), redis)
+                            .queues.insert((QueueKind::Output, queue, is_glob));
+                    }
+                    None => {
+                        queues.insert((QueueKind::Output, queue, is_glob));
+                    }
+                }
+            }
+        }
+        server(&mut servers, label, redis).queues.extend(queues);
+    }
+    Ok(servers)
+}
+
+/// the lists whose names match a glob
+pub fn matching_lists(con: &mut RedisConnection, glob: &str) -> Result<Vec<String>, RescError> {
+    let mut lists = Vec::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(glob)
+            .arg("COUNT")
+            .arg(1000)
+            .query(con)?;
+        for key in keys {
+            let key_type: String = redis::cmd("TYPE").arg(&key).query(con)?;
+            if key_type == "list" {
+                lists.push(key);
+            }
+        }
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    lists.sort();
+    Ok(lists)
+}
+
+/// Print the lengths of the queues referenced by the configuration,
+/// highlighting the taken queues which aren't empty.
+///
+/// For an output queue whose name depends on the task, all the
+/// queues matching its pattern are counted.
+pub fn print_queues(conf: &Conf) -> Result<(), RescError> {
+    for server in referenced_queues(conf)? {
+        if server.queues.is_empty() {
+            continue;
+        }
+        println!("{}", &server.label);
+        let mut con = server.redis.open_connection()?;
+        let width = server.queues.iter().map(|(_, queue, _)| queue.len()).max().unwrap_or(0);
+        for (kind, queue, is_glob) in &server.queues {
+            if *is_glob {
+                let lists = matching_lists(&mut con, queue)?;
+                let mut len = 0;
+                for list in &lists {
+                    let list_len: usize = con.llen(list)?;
+                    len += list_len;
+                }
+                println!(
+                    "  {:<6} {:<width$} {} task(s) in {} queue(s)",
+                    kind.name(), queue, len, lists.len(), width = width,
+                );
+                continue;
+            }
+            let len: usize = con.llen(queue)?;
+            let warning = if *kind == QueueKind::Taken && len > 0 { "  <- not empty" } else { "" };
+            println!("  {:<6} {:<width$} {}{}", kind.name(), queue, len, warning, width = width);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use {
+    crate::*,
+    serde::{de, Deserialize, Deserializer},
+    std::str::FromStr,
+};
+
+/// A maximal rate of tasks written to a queue or stream,
+/// like "10/s" or "500/m".
+///
+/// The limit is shared by all rules writing to the same target,
+/// and by all the instances of resc using the same Redis server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// the number of tasks allowed in a period
+    pub count: u32,
+    /// the period, in seconds
+    pub period: f64,
+}
+
+impl RateLimit {
+
+    /// the key of the bucket of a target
+    pub fn bucket(target: &TaskTarget) -> String {
+        format!("resc/rate_limit/{}", target.name())
+    }
+
+    /// the number of seconds between two tokens
+    pub fn interval(&self) -> f64 {
+        self.period / self.count as f64
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!(
+            "invalid rate_limit {:?}, expected <n>/s, <n>/m, or <n>/h",
+            s,
+        );
+        let (count, unit) = s.split_once('/').ok_or_else(invalid)?;
+        let count = count.trim().parse().map_err(|_| invalid())?;
+        if count == 0 {
+            return Err(invalid());
+        }
+        let period = match unit.trim() {
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        Ok(Self { count, period })
+    }
+}
+
+impl<'de> Deserialize<'de> for RateLimit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
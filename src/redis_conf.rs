@@ -0,0 +1,195 @@
+use {
+    crate::*,
+    log::*,
+    redis::{
+        self,
+        cluster::ClusterClientBuilder,
+        ClientTlsConfig,
+        Connection,
+        ConnectionAddr,
+        ConnectionInfo,
+        IntoConnectionInfo,
+        RedisResult,
+        TlsCertificates,
+    },
+    serde::Deserialize,
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Redis access configuration
+///
+/// Either `url`, `sentinels`, or `cluster_nodes` must be provided.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConf {
+
+    /// the url of the server, e.g. "redis://127.0.0.1/".
+    /// When sentinels are used, only the credentials and the
+    /// database of this url are used.
+    pub url: Option<String>,
+
+    /// the urls of the sentinels, when the server is
+    /// managed by Redis Sentinel
+    #[serde(default)]
+    pub sentinels: Vec<String>,
+
+    /// the name of the master, as known by the sentinels
+    pub master_name: Option<String>,
+
+    /// the urls of some nodes of the Redis Cluster, when
+    /// connecting to a cluster
+    #[serde(default)]
+    pub cluster_nodes: Vec<String>,
+
+    /// whether the certificate of the server must be accepted
+    /// without verification, when connecting with TLS (that is
+    /// with a `rediss://` url)
+    #[serde(default)]
+    pub tls_insecure: bool,
+
+    /// the path to the PEM file of the certificate authority of
+    /// the server, when it's not trusted by the system
+    pub tls_ca: Option<PathBuf>,
+
+    /// the paths to the PEM files of the certificate and the
+    /// private key of the client, when the server checks them
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+
+}
+
+impl RedisConf {
+
+    /// open a new connection, asking the sentinels, if any,
+    /// for the address of the current master
+    pub fn open_connection(&self) -> Result<RedisConnection, RescError> {
+        if !self.cluster_nodes.is_empty() {
+            let nodes = self.cluster_nodes.iter()
+                .map(|url| self.parse_url(url))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut builder = ClusterClientBuilder::new(nodes);
+            if let Some(certificates) = self.tls_certificates()? {
+                builder = builder.certs(certificates);
+            }
+            let client = builder.build()?;
+            return Ok(RedisConnection::Cluster(Box::new(client.get_connection()?)));
+        }
+        let client = self.client(self.connection_info()?)?;
+        Ok(RedisConnection::Single(client.get_connection()?))
+    }
+
+    /// open a connection to a single server, for a subscription.
+    ///
+    /// In a cluster, messages are propagated to all nodes, so
+    /// the connection is made to the first node.
+    pub fn open_pubsub_connection(&self) -> Result<Connection, RescError> {
+        let info = match self.cluster_nodes.first() {
+            Some(url) => self.parse_url(url)?,
+            None => self.connection_info()?,
+        };
+        let client = self.client(info)?;
+        Ok(client.get_connection()?)
+    }
+
+    /// build a client, with the certificates of the
+    /// configuration when the connection uses TLS
+    fn client(&self, info: ConnectionInfo) -> Result<redis::Client, RescError> {
+        let tls = matches!(info.addr, ConnectionAddr::TcpTls { .. });
+        match self.tls_certificates()?.filter(|_| tls) {
+            Some(certificates) => Ok(redis::Client::build_with_tls(info, certificates)?),
+            None => Ok(redis::Client::open(info)?),
+        }
+    }
+
+    /// read the certificates given in the configuration, if any
+    fn tls_certificates(&self) -> Result<Option<TlsCertificates>, ConfError> {
+        let client_tls = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some(ClientTlsConfig {
+                client_cert: read_tls_file(cert)?,
+                client_key: read_tls_file(key)?,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(ConfError::IncompleteTlsClientCert);
+            }
+        };
+        let root_cert = self.tls_ca.as_deref().map(read_tls_file).transpose()?;
+        if client_tls.is_none() && root_cert.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TlsCertificates { client_tls, root_cert }))
+    }
+
+    /// parse an url, applying the TLS settings
+    fn parse_url(&self, url: &str) -> RedisResult<ConnectionInfo> {
+        let mut info = url.into_connection_info()?;
+        if let ConnectionAddr::TcpTls { insecure, .. } = &mut info.addr {
+            *insecure = self.tls_insecure;
+        }
+        Ok(info)
+    }
+
+    fn connection_info(&self) -> Result<ConnectionInfo, RescError> {
+        if self.sentinels.is_empty() {
+            let url = self.url.as_ref().ok_or(ConfError::MissingRedisUrl)?;
+            return Ok(self.parse_url(url)?);
+        }
+        let master_name = self.master_name.as_ref().ok_or(ConfError::MissingMasterName)?;
+        let url_info = self.url.as_ref()
+            .map(|url| self.parse_url(url))
+            .transpose()?;
+        let (host, port) = self.resolve_master(master_name)?;
+        let addr = match url_info.as_ref().map(|info| &info.addr) {
+            Some(ConnectionAddr::TcpTls { .. }) => ConnectionAddr::TcpTls {
+                host,
+                port,
+                insecure: self.tls_insecure,
+                tls_params: None,
+            },
+            _ => ConnectionAddr::Tcp(host, port),
+        };
+        Ok(ConnectionInfo {
+            addr,
+            redis: url_info.map(|info| info.redis).unwrap_or_default(),
+        })
+    }
+
+    /// ask the sentinels, in order, for the address of the master
+    fn resolve_master(&self, master_name: &str) -> Result<(String, u16), RescError> {
+        for sentinel in &self.sentinels {
+            let addr: RedisResult<Option<(String, u16)>> = self.parse_url(sentinel)
+                .and_then(redis::Client::open)
+                .and_then(|client| client.get_connection())
+                .and_then(|mut con| {
+                    redis::cmd("SENTINEL")
+                        .arg("get-master-addr-by-name")
+                        .arg(master_name)
+                        .query(&mut con)
+                });
+            match addr {
+                Ok(Some((host, port))) => {
+                    info!("sentinel {:?} gives master {:?} at {}:{}", sentinel, master_name, host, port);
+                    return Ok((host, port));
+                }
+                Ok(None) => {
+                    warn!("sentinel {:?} doesn't know master {:?}", sentinel, master_name);
+                }
+                Err(e) => {
+                    warn!("sentinel {:?} can't be queried: {}", sentinel, e);
+                }
+            }
+        }
+        Err(RescError::MasterNotFound(master_name.to_string()))
+    }
+
+}
+
+fn read_tls_file(path: &Path) -> Result<Vec<u8>, ConfError> {
+    fs::read(path).map_err(|source| ConfError::TlsFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
@@ -0,0 +1,92 @@
+use {
+    redis::{
+        cluster::ClusterConnection,
+        Cmd,
+        Connection,
+        ConnectionLike,
+        Pipeline,
+        RedisResult,
+        Value,
+    },
+};
+
+/// A connection to either a single Redis server or a Redis Cluster.
+///
+/// All Redis commands are available on both through the
+/// `redis::Commands` trait.
+pub enum RedisConnection {
+    Single(Connection),
+    Cluster(Box<ClusterConnection>),
+}
+
+impl RedisConnection {
+    /// run the commands of a pipeline, in a MULTI/EXEC transaction on
+    /// a single server, or one after the other on a cluster, where
+    /// the keys of a transaction would have to be in the same slot.
+    ///
+    /// The commands of the pipeline must not be ignored, so that there's
+    /// one returned value per command.
+    pub fn run_transaction(&mut self, pipe: &Pipeline) -> RedisResult<Vec<Value>> {
+        match self {
+            Self::Single(con) => {
+                let mut pipe = pipe.clone();
+                pipe.atomic().query(con)
+            }
+            Self::Cluster(con) => {
+                pipe.cmd_iter()
+                    .map(|cmd| cmd.query(&mut **con))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        match self {
+            Self::Single(con) => con.req_packed_command(cmd),
+            Self::Cluster(con) => con.req_packed_command(cmd),
+        }
+    }
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        match self {
+            Self::Single(con) => con.req_packed_commands(cmd, offset, count),
+            Self::Cluster(con) => con.req_packed_commands(cmd, offset, count),
+        }
+    }
+    fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        match self {
+            Self::Single(con) => con.req_command(cmd),
+            Self::Cluster(con) => con.req_command(cmd),
+        }
+    }
+    fn supports_pipelining(&self) -> bool {
+        match self {
+            Self::Single(con) => con.supports_pipelining(),
+            Self::Cluster(con) => con.supports_pipelining(),
+        }
+    }
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Single(con) => con.get_db(),
+            Self::Cluster(con) => con.get_db(),
+        }
+    }
+    fn check_connection(&mut self) -> bool {
+        match self {
+            Self::Single(con) => con.check_connection(),
+            Self::Cluster(con) => con.check_connection(),
+        }
+    }
+    fn is_open(&self) -> bool {
+        match self {
+            Self::Single(con) => con.is_open(),
+            Self::Cluster(con) => con.is_open(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use {
+    crate::*,
+    log::*,
+    redis::Commands,
+    serde::Deserialize,
+    std::collections::HashMap,
+};
+
+/// The Redis command of a redis fetcher
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisFetchCommand {
+    /// one result per member of a set
+    Smembers,
+    /// one result per element of a list
+    Lrange,
+    /// one result with a property per field of a hash
+    Hgetall,
+    /// one result per member of a sorted set, with its score
+    Zrange,
+}
+
+/// A fetcher reading a key on the Redis server of the watcher
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisFetcher {
+    pub returns: String,
+
+    /// the key to read
+    pub key: Pattern,
+
+    pub command: RedisFetchCommand,
+}
+
+impl RedisFetcher {
+    fn returned_key(&self, key: &str) -> String {
+        format!("{}.{}", self.returns, key)
+    }
+
+    pub fn results(
+        &self,
+        props: &HashMap<String, String>,
+        context: &mut FetchContext<'_>,
+    ) -> Result<Vec<FetchResult>, FetchError> {
+        let key = self.key.inject(props);
+        info!("  reading redis key: {:?}", &key);
+        let con = &mut *context.con;
+        let results = match self.command {
+            RedisFetchCommand::Smembers => {
+                let members: Vec<String> = con.smembers(&key)?;
+                members.into_iter().map(|m| FetchResult::from_value(&self.returns, m)).collect()
+            }
+            RedisFetchCommand::Lrange => {
+                let elements: Vec<String> = con.lrange(&key, 0, -1)?;
+                elements.into_iter().map(|e| FetchResult::from_value(&self.returns, e)).collect()
+            }
+            RedisFetchCommand::Hgetall => {
+                let fields: HashMap<String, String> = con.hgetall(&key)?;
+                if fields.is_empty() {
+                    vec![] // the key doesn't exist
+                } else {
+                    let props = fields.into_iter()
+                        .map(|(field, value)| (self.returned_key(&field), value))
+                        .collect();
+                    vec![FetchResult { props }]
+                }
+            }
+            RedisFetchCommand::Zrange => {
+                let members: Vec<(String, String)> = con.zrange_withscores(&key, 0, -1)?;
+                members.into_iter()
+                    .map(|(member, score)| {
+                        let mut result = FetchResult::from_value(&self.returns, member);
+                        result.props.insert(self.returned_key("score"), score);
+                        result
+                    })
+                    .collect()
+            }
+        };
+        Ok(results)
+    }
+}
@@ -0,0 +1,75 @@
+use {
+    regex::Regex,
+    serde::{
+        de::{
+            self,
+            value::SeqAccessDeserializer,
+            SeqAccess,
+            Visitor,
+        },
+        Deserialize,
+        Deserializer,
+    },
+    std::{collections::HashMap, fmt},
+};
+
+/// One or several regexes, given in the configuration
+/// either as a single string or as an array of strings
+#[derive(Debug, Clone)]
+pub struct RegexList {
+    pub regexes: Vec<Regex>,
+}
+
+impl RegexList {
+    /// tell whether any of the regexes matches
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regexes.iter().any(|regex| regex.is_match(text))
+    }
+    /// the names of the capture groups of all the regexes
+    pub fn capture_names(&self) -> impl Iterator<Item = &str> {
+        self.regexes.iter().flat_map(|regex| regex.capture_names().flatten())
+    }
+    /// insert in the properties the named captures of all the matching
+    /// regexes, a capture of a regex taking precedence over the ones
+    /// of the following regexes
+    pub fn insert_captures(&self, text: &str, props: &mut HashMap<String, String>) {
+        for regex in &self.regexes {
+            let caps = match regex.captures(text) {
+                Some(caps) => caps,
+                None => continue,
+            };
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = caps.name(name) {
+                    props.entry(name.to_string()).or_insert_with(|| value.as_str().to_string());
+                }
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        struct RegexListVisitor;
+        impl<'de> Visitor<'de> for RegexListVisitor {
+            type Value = RegexList;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a regex or an array of regexes")
+            }
+            fn visit_str<E>(self, s: &str) -> Result<RegexList, E>
+                where E: de::Error
+            {
+                let regex = Regex::new(s).map_err(de::Error::custom)?;
+                Ok(RegexList { regexes: vec![regex] })
+            }
+            fn visit_seq<A>(self, seq: A) -> Result<RegexList, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let regexes = serde_regex::deserialize(SeqAccessDeserializer::new(seq))?;
+                Ok(RegexList { regexes })
+            }
+        }
+        deserializer.deserialize_any(RegexListVisitor)
+    }
+}
@@ -0,0 +1,106 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    redis::{Commands, Script},
+    regex::Regex,
+    std::{thread, time::Duration},
+};
+
+/// the number of tasks replayed per second, when not given
+pub const DEFAULT_REPLAY_RATE: f64 = 10.0;
+
+lazy_static! {
+    // Moves the task at the tail of KEYS[1] to the head of KEYS[2],
+    // provided it's still ARGV[1], and returns 1 if it was moved.
+    static ref MOVE_SCRIPT: Script = Script::new(r"
+        if redis.call('LINDEX', KEYS[1], -1) ~= ARGV[1] then
+            return 0
+        end
+        redis.call('RPOPLPUSH', KEYS[1], KEYS[2])
+        return 1
+    ");
+}
+
+/// What to replay, and how
+#[derive(Debug)]
+pub struct Replay<'a> {
+    /// the dead letter or unmatched queue
+    pub from: &'a str,
+    /// the input queue where to push the tasks, when it
+    /// can't be deduced from the configuration
+    pub queue: Option<&'a str>,
+    /// the maximal number of tasks to replay
+    pub limit: Option<usize>,
+    /// the regex the replayed tasks must match
+    pub filter: Option<&'a Regex>,
+    /// the maximal number of tasks replayed per second
+    pub rate: f64,
+}
+
+/// the watcher whose input receives the replayed tasks: the one
+/// reading the given queue, or the one pushing to `from` its
+/// unmatched or failed tasks
+fn target_watcher<'c>(conf: &'c Conf, replay: &Replay<'_>) -> Result<&'c WatcherConf, ConfError> {
+    if let Some(queue) = replay.queue {
+        return conf.watchers.iter()
+            .find(|watcher| watcher.input_queue.as_deref() == Some(queue))
+            .ok_or_else(|| ConfError::UnknownInputQueue(queue.to_string()));
+    }
+    let from = replay.from;
+    let watchers: Vec<&WatcherConf> = conf.watchers.iter()
+        .filter(|watcher| watcher.input_queue.is_some())
+        .filter(|watcher| {
+            watcher.unmatched_queue.as_deref() == Some(from)
+                || watcher.rules.iter().any(|rule| {
+                    matches!(&rule.on_failure, FailurePolicy::DeadLetter(queue) if queue == from)
+                })
+        })
+        .collect();
+    match watchers.len() {
+        0 => Err(ConfError::UnknownReplaySource(from.to_string())),
+        1 => Ok(watchers[0]),
+        _ => Err(ConfError::AmbiguousReplayTarget(from.to_string())),
+    }
+}
+
+/// Move tasks from a dead letter or unmatched queue back to the
+/// input queue of the watcher, at most `rate` per second, and
+/// return the input queue and the number of tasks moved.
+///
+/// The tasks not matching the filter stay in the queue, but
+/// are moved to its head.
+pub fn replay(conf: &Conf, replay: &Replay<'_>) -> Result<(String, usize), RescError> {
+    let watcher = target_watcher(conf, replay)?;
+    let queue = watcher.input_queue.clone().unwrap(); // filtered in target_watcher
+    let redis_conf = watcher.redis.as_ref().unwrap_or(&conf.redis);
+    let mut con = redis_conf.open_connection()?;
+    let period = Duration::from_secs_f64(1.0 / replay.rate);
+    // every task present at start is examined at most once, even
+    // when the ones not matching the filter come back to the head
+    let len: usize = con.llen(replay.from)?;
+    let mut count = 0;
+    for _ in 0..len {
+        if replay.limit.is_some_and(|limit| count >= limit) {
+            break;
+        }
+        let task: Option<String> = con.lindex(replay.from, -1)?;
+        let task = match task {
+            Some(task) => task,
+            None => break,
+        };
+        let selected = replay.filter.is_none_or(|filter| filter.is_match(&task));
+        let destination = if selected { &queue } else { replay.from };
+        let moved: i32 = MOVE_SCRIPT
+            .key(replay.from)
+            .key(destination)
+            .arg(&task)
+            .invoke(&mut con)?;
+        if moved == 1 && selected {
+            info!("{:?} replayed in queue {:?}", &task, &queue);
+            count += 1;
+            thread::sleep(period);
+        }
+    }
+    Ok((queue, count))
+}
@@ -1,15 +1,36 @@
 use {
     crate::*,
+    chrono::Local,
     log::*,
-    regex::Regex,
     serde::Deserialize,
-    std::collections::HashMap,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+        time::Instant,
+    },
 };
 
 
+/// How the results of several fetchers are combined
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchMode {
+    /// each result of each fetcher gives tasks
+    #[default]
+    Concat,
+    /// each combination of one result per fetcher gives
+    /// tasks, with the properties of all those results
+    Product,
+}
+
 /// a rule, defined by a condition (the "on" pattern)
 /// and what to do with the matching tasks
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Rule {
 
     /// the name, unused for now, but having it in the JSON
@@ -18,11 +39,41 @@ pub struct Rule {
     #[serde(default = "Rule::default_name")]
     pub name: String,
 
+    /// when false, the rule is ignored, which allows keeping
+    /// it in the configuration without applying it
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// rules of higher priority are applied first, and only the
+    /// first matching one is applied when the watcher's match_mode
+    /// is "first" (default: 0)
+    #[serde(default)]
+    pub priority: i32,
+
+    /// when false, the rules after this one aren't evaluated
+    /// for the tasks it matches
+    #[serde(rename = "continue", default = "Rule::default_continue")]
+    pub continue_matching: bool,
+
     /// the input task parser. It checks the rule applies to
     /// the task and it extracts the token which will be used
-    /// to generate the output task
-    #[serde(with = "serde_regex", alias = "on")]
-    pub on_regex: Regex,
+    /// to generate the output task.
+    /// It may be an array of regexes, any of them triggering the rule
+    #[serde(alias = "on")]
+    pub on_regex: RegexList,
+
+    /// the optional exclusion patterns: the rule doesn't apply
+    /// to the tasks matching any of them
+    pub unless: Option<RegexList>,
+
+    /// when the rule is active: out of this window, it
+    /// doesn't match any task
+    pub active: Option<TimeWindow>,
+
+    /// whether the task is a JSON document, whose values are then
+    /// available as `payload.some.path` properties
+    #[serde(default)]
+    pub task_format: TaskFormat,
 
     /// The optional fetchers which may query some additional
     /// token for generation of the output task
@@ -34,48 +85,334 @@ pub struct Rule {
     #[serde(alias = "make")]
     pub makers: Makers,
 
+    /// what to do with the task when the rule fails
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+
+    /// the maximal number of fetchers of the rule
+    /// running at the same time
+    #[serde(default = "Rule::default_fetch_concurrency")]
+    pub fetch_concurrency: usize,
+
+    /// how the results of the fetchers are combined
+    #[serde(default)]
+    pub fetch_mode: FetchMode,
+
+    /// whether a variable without value in a make pattern makes
+    /// the rule fail, instead of being replaced with a placeholder.
+    /// When not set, the global `strict_patterns` applies
+    pub strict_patterns: Option<bool>,
+
+    /// an optional script computing derived properties from the
+    /// captured and fetched ones, before the filter and the makers
+    pub transform: Option<Transform>,
+
+    /// a condition the properties, including the fetched
+    /// ones, must verify for tasks to be made
+    #[serde(alias = "when")]
+    pub filter: Option<Condition>,
+
+    /// when set, a task matching the rule less than this many
+    /// milliseconds after another one with the same debounce
+    /// key is ignored by the rule
+    pub debounce_ms: Option<u64>,
+
+    /// the key identifying the tasks debounced together
+    /// (default: the input task)
+    pub debounce_key: Option<Pattern>,
+
+    /// what identifies the rule in the keys of its debounce latches,
+    /// its watcher and its index, set when the configuration is read
+    #[serde(skip)]
+    pub debounce_scope: String,
+
+    /// when true, the tasks the rule would make are logged
+    /// instead of being written
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// examples of tasks, with the tasks the rule
+    /// must make from them, run by `resc test`
+    #[serde(default)]
+    pub tests: Vec<RuleTest>,
+
+    /// the input queue of the watcher, if it reads a queue,
+    /// set when the configuration is read
+    #[serde(skip)]
+    pub input_queue: Option<String>,
+
 }
 
 impl Rule {
     pub fn default_name() -> String {
         "<anonymous rule>".into()
     }
+    pub fn default_continue() -> bool {
+        true
+    }
+    pub fn default_fetch_concurrency() -> usize {
+        4
+    }
     pub fn is_match(&self, task: &str) -> bool {
+        self.is_task_match(task)
+            && self.active.as_ref().is_none_or(|active| active.contains(Local::now()))
+    }
+    /// whether the task matches the regexes of the rule,
+    /// whatever the time
+    pub fn is_task_match(&self, task: &str) -> bool {
         self.on_regex.is_match(task)
+            && !self.unless.as_ref().is_some_and(|unless| unless.is_match(task))
     }
     /// Assuming the rule matches, computes the rule results
-    /// (there's only one RuleResult when no fetcher is involved)
-    pub fn results(&self, task: &str) -> Result<Vec<RuleResult>, RescError> {
+    /// (there's only one RuleResult when no fetcher is involved),
+    /// with the debounce latch taken for the task, if any, which
+    /// must be released if the results can't be written
+    pub fn results(
+        &self,
+        task: &str,
+        context: &mut FetchContext<'_>,
+    ) -> Result<(Vec<RuleResult>, Option<String>), RescError> {
+        let props = self.props(task)?;
+        let latch = self.latch(&props, context);
+        if let Some(latch) = &latch {
+            if !self.take_latch(latch, context)? {
+                debug!("    debounced");
+                return Ok((Vec::new(), None));
+            }
+        }
+        match self.fetch_and_combine(props, context) {
+            Ok(results) => Ok((results, latch)),
+            Err(e) => {
+                if let Some(latch) = &latch {
+                    // so that the task isn't debounced when it's retried
+                    release_latch(context.con, latch);
+                }
+                Err(e)
+            }
+        }
+    }
+    fn fetch_and_combine(
+        &self,
+        props: HashMap<String, String>,
+        context: &mut FetchContext<'_>,
+    ) -> Result<Vec<RuleResult>, RescError> {
+        let all_fetch_results = if self.fetchers.is_empty() {
+            Vec::new()
+        } else {
+            let all_fetch_results = self.fetch_all(&props, context)?;
+            debug!("    -> fetch results {:#?}", &all_fetch_results);
+            all_fetch_results
+        };
+        self.combine(props, all_fetch_results)
+    }
+    /// the properties of a matching task: the input task, the input
+    /// queue, the captures of the `on` regexes, and the payload, if any
+    pub fn props(&self, task: &str) -> Result<HashMap<String, String>, RescError> {
         // props will contain the token usable for generating
         // the task name, output queue and output set
         let mut props: HashMap<String, String> = HashMap::new();
         props.insert("input_task".to_owned(), task.to_owned());
-        let caps = self.on_regex.captures(task).unwrap();
+        if let Some(queue) = &self.input_queue {
+            props.insert("input_queue".to_owned(), queue.clone());
+        }
+        self.on_regex.insert_captures(task, &mut props);
+        if self.task_format == TaskFormat::Json {
+            let payload: serde_json::Value = serde_json::from_str(task)
+                .map_err(|e| RescError::InvalidJsonTask(e.to_string()))?;
+            insert_json_props("payload".to_string(), &payload, &mut props);
+        }
+        Ok(props)
+    }
+    /// make the rule results from the properties of the task
+    /// and the results of the fetchers, in the order of the fetchers
+    pub fn combine(
+        &self,
+        props: HashMap<String, String>,
+        all_fetch_results: Vec<Vec<FetchResult>>,
+    ) -> Result<Vec<RuleResult>, RescError> {
         let mut results = Vec::new();
-        for groupname in self.on_regex.capture_names().flatten() {
-            if let Some(value) = caps.name(groupname) {
-                props.insert(groupname.to_string(), value.as_str().to_string());
-            }
+        if self.fetchers.is_empty() {
+            self.make(&props, &mut results)?;
+            return Ok(results);
         }
-        if !self.fetchers.is_empty() {
-            // if there are fetchers, we'll fetch all the possible results
-            // and generate a ruleresult per fetchresult
-            for fetcher in &self.fetchers {
-                let fetch_results = fetcher.results(&props)?;
-                debug!("    -> fetch results {:#?}", &fetch_results);
-                for mut fetch_result in fetch_results {
-                    // we inject the parent properties
-                    // This is heavy but makes the whole simpler
-                    for (key, value) in &props {
-                        fetch_result.props.insert(key.clone(), value.clone());
+        // if there are fetchers, we generate a ruleresult per fetchresult
+        match self.fetch_mode {
+            FetchMode::Concat => {
+                for fetch_results in all_fetch_results {
+                    for mut fetch_result in fetch_results {
+                        // we inject the parent properties
+                        // This is heavy but makes the whole simpler
+                        for (key, value) in &props {
+                            fetch_result.props.insert(key.clone(), value.clone());
+                        }
+                        trace!(" merged: {:#?}", &fetch_result.props);
+                        self.make(&fetch_result.props, &mut results)?;
                     }
-                    trace!(" merged: {:#?}", &fetch_result.props);
-                    self.makers.make(&fetch_result.props, &mut results);
                 }
             }
-        } else {
-            self.makers.make(&props, &mut results);
+            FetchMode::Product => {
+                let mut combinations = vec![props];
+                for fetch_results in all_fetch_results {
+                    combinations = combinations.iter()
+                        .flat_map(|combination| {
+                            fetch_results.iter().map(move |fetch_result| {
+                                let mut merged = combination.clone();
+                                merged.extend(fetch_result.props.clone());
+                                merged
+                            })
+                        })
+                        .collect();
+                }
+                for combination in &combinations {
+                    trace!(" combined: {:#?}", combination);
+                    self.make(combination, &mut results)?;
+                }
+            }
         }
         Ok(results)
     }
+    /// the key of the latch of the debounce key, when the rule is
+    /// debounced. A dry run rule has no latch, as it writes nothing
+    fn latch(
+        &self,
+        props: &HashMap<String, String>,
+        context: &FetchContext<'_>,
+    ) -> Option<String> {
+        if self.debounce_ms.is_none() || self.dry_run || context.dry_run {
+            return None;
+        }
+        let key = match &self.debounce_key {
+            Some(pattern) => pattern.inject(props),
+            None => props["input_task"].clone(),
+        };
+        Some(format!("resc/debounce/{}/{}", self.debounce_scope, key))
+    }
+    /// try to set the latch, and return whether it was free
+    fn take_latch(
+        &self,
+        latch: &str,
+        context: &mut FetchContext<'_>,
+    ) -> Result<bool, RescError> {
+        let taken: Option<String> = redis::cmd("SET")
+            .arg(latch)
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.debounce_ms)
+            .query(context.con)?;
+        let free = taken.is_some();
+        if !free {
+            if let Some(metrics) = context.metrics {
+                metrics.count("rule.debounced", 1, &[("rule", &self.name)]);
+            }
+        }
+        Ok(free)
+    }
+    /// make the tasks, unless the filter rejects the properties,
+    /// which are first transformed by the script of the rule
+    fn make(
+        &self,
+        props: &HashMap<String, String>,
+        results: &mut Vec<RuleResult>,
+    ) -> Result<(), RescError> {
+        let transformed;
+        let props = match &self.transform {
+            Some(transform) => {
+                transformed = transform.apply(props)?;
+                trace!(" transformed: {:#?}", &transformed);
+                &transformed
+            }
+            None => props,
+        };
+        if let Some(filter) = &self.filter {
+            if !filter.eval(props) {
+                debug!("    filtered out by {:?}", filter.src);
+                return Ok(());
+            }
+        }
+        self.makers.make(props, self.strict_patterns.unwrap_or(false), results)
+    }
+    /// run all the fetchers, at most `fetch_concurrency` at a time,
+    /// and return their results in the order of the fetchers.
+    ///
+    /// The fetchers using the connection of the watcher run
+    /// one after the other, on the current thread.
+    fn fetch_all(
+        &self,
+        props: &HashMap<String, String>,
+        context: &mut FetchContext<'_>,
+    ) -> Result<Vec<Vec<FetchResult>>, FetchError> {
+        let metrics = context.metrics;
+        let span = context.span;
+        let standalone_count = self.fetchers.iter().filter(|f| !f.uses_connection()).count();
+        let workers = standalone_count.min(self.fetch_concurrency);
+        if workers <= 1 || self.fetchers.len() <= 1 {
+            return self.fetchers.iter()
+                .map(|fetcher| self.timed(fetcher, metrics, span, || fetcher.results(props, context)))
+                .collect();
+        }
+        let slots: Vec<Mutex<Option<_>>> = self.fetchers.iter()
+            .map(|_| Mutex::new(None))
+            .collect();
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let fetcher = match self.fetchers.get(i) {
+                        Some(fetcher) => fetcher,
+                        None => break,
+                    };
+                    if fetcher.uses_connection() {
+                        continue; // run on the current thread
+                    }
+                    let res = self.timed(fetcher, metrics, span, || {
+                        fetcher.standalone_results(props).unwrap() // it doesn't use the connection
+                    });
+                    *slots[i].lock().unwrap() = Some(res);
+                });
+            }
+            for (i, fetcher) in self.fetchers.iter().enumerate() {
+                if fetcher.uses_connection() {
+                    let res = self.timed(fetcher, metrics, span, || fetcher.results(props, context));
+                    *slots[i].lock().unwrap() = Some(res);
+                }
+            }
+        });
+        slots.into_iter()
+            .map(|slot| slot.into_inner().unwrap().unwrap()) // all slots are filled
+            .collect()
+    }
+    /// run a fetch, recording its duration and tracing it
+    fn timed<T>(
+        &self,
+        fetcher: &Fetcher,
+        metrics: Option<&Metrics>,
+        span: &TraceSpan,
+        fetch: impl FnOnce() -> Result<T, FetchError>,
+    ) -> Result<T, FetchError> {
+        let fetch_span = span.child("fetch");
+        fetch_span.set_attribute("fetcher", fetcher.returns());
+        let start = Instant::now();
+        let res = fetch();
+        if let Err(e) = &res {
+            fetch_span.set_error(e);
+        }
+        if let Some(metrics) = metrics {
+            metrics.timing(
+                "fetch.duration",
+                start.elapsed(),
+                &[("rule", &self.name), ("fetcher", fetcher.returns())],
+            );
+        }
+        res
+    }
+}
+
+/// remove a debounce latch taken for a task whose
+/// results couldn't be computed or written
+pub fn release_latch(con: &mut RedisConnection, latch: &str) {
+    if let Err(e) = redis::cmd("DEL").arg(latch).query::<()>(con) {
+        warn!("debounce latch {:?} can't be released: {}", latch, e);
+    }
 }
@@ -1,4 +1,94 @@
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
 
+/// where a generated task must be written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TaskTarget {
+
+    /// a list, in which the task is pushed
+    Queue(String),
+
+    /// a stream, in which an entry is added, with the task
+    /// and the properties as fields
+    Stream {
+        stream: String,
+        fields: Vec<(String, String)>,
+    },
+
+    /// a sorted set, in which the task is added with a score
+    Zset {
+        zset: String,
+        score: f64,
+    },
+
+}
+
+impl TaskTarget {
+    /// the name of the queue, stream, or sorted set
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Queue(queue) => queue,
+            Self::Stream { stream, .. } => stream,
+            Self::Zset { zset, .. } => zset,
+        }
+    }
+}
+
+impl fmt::Display for TaskTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Queue(queue) => write!(f, "queue {:?}", queue),
+            Self::Stream { stream, .. } => write!(f, "stream {:?}", stream),
+            Self::Zset { zset, .. } => write!(f, "sorted set {:?}", zset),
+        }
+    }
+}
+
+/// the end of a queue where tasks are pushed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushSide {
+    /// the head, with LPUSH, for consumers popping
+    /// from the right
+    #[default]
+    Left,
+    /// the tail, with RPUSH, for consumers popping
+    /// from the left
+    Right,
+}
+
+impl PushSide {
+    pub fn is_left(&self) -> bool {
+        *self == Self::Left
+    }
+    /// the Redis command pushing on this side
+    pub fn command(self) -> &'static str {
+        match self {
+            Self::Left => "LPUSH",
+            Self::Right => "RPUSH",
+        }
+    }
+}
+
+/// How a task is deduplicated: it's not written when
+/// it's already in the set, or when the key exists
+#[derive(Debug, Clone)]
+pub enum Dedup {
+    /// a sorted set of the tasks, scored with the time they were
+    /// added, and where they expire after the ttl, if any
+    Set {
+        set: String,
+        ttl: Option<u64>,
+    },
+    /// a key set with `SET NX`, with an expiration if a ttl is given
+    Key {
+        key: String,
+        ttl: Option<u64>,
+    },
+}
 
 /// result of applying a rule to a task
 #[derive(Debug)]
@@ -7,11 +97,32 @@ pub struct RuleResult {
     /// the task to generate
     pub task: String,
 
-    /// the queue where to write the task
-    pub queue: String,
+    /// where to write the task
+    pub target: TaskTarget,
+
+    /// the side of the queue where the task is pushed
+    pub push: PushSide,
+
+    /// how to check the task isn't yet in the queue
+    pub dedup: Option<Dedup>,
+
+    /// the name of the Redis server where to write, when
+    /// not the one of the watcher
+    pub redis: Option<String>,
+
+    /// the delay, in seconds, before the task is written
+    pub delay: Option<f64>,
+
+    /// the maximal rate of writes to the target
+    pub rate_limit: Option<RateLimit>,
+
+    /// the length of the queue beyond which the task is parked
+    pub max_queue_len: Option<usize>,
+
+    /// the auxiliary writes to do when the task is written
+    pub effects: Vec<Effect>,
 
-    /// the sorted set where to check the task
-    /// isn't yet in the queue
-    pub set: Option<String>,
+    /// the countdown of the parents to wait for, if any
+    pub join: Option<Join>,
 
 }
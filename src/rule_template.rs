@@ -0,0 +1,187 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    regex::{Captures, Regex},
+    serde::{de::DeserializeOwned, Deserialize},
+    serde_json::{Map, Value},
+    std::{
+        collections::{HashMap, HashSet},
+        mem,
+        path::Path,
+    },
+};
+
+lazy_static! {
+    static ref PARAM_REGEX: Regex = Regex::new(r"\$\{param:(\w+)\}").unwrap();
+}
+
+/// The rule templates of the configuration: parameterized rule
+/// skeletons, instantiated in the rules of the watchers by entries
+/// like `{use: "some-template", with: {kind: "video"}}`.
+///
+/// In the strings of the template, `${param:kind}` is replaced with
+/// the value given in `with`. The other properties of the entry
+/// override the ones of the template.
+#[derive(Debug, Default)]
+pub struct RuleTemplates {
+    templates: HashMap<String, Value>,
+}
+
+impl RuleTemplates {
+    /// get the templates defined in a configuration read as a value
+    pub fn from_conf(conf: &Value) -> Result<Self, ConfError> {
+        let templates = match conf.get("templates") {
+            Some(templates) => HashMap::<String, Value>::deserialize(templates)
+                .map_err(ConfError::Invalid)?,
+            None => HashMap::new(),
+        };
+        Ok(Self { templates })
+    }
+    /// deserialize the content of a file, already read as a value,
+    /// after having instantiated the templates it uses and replaced
+    /// the environment variables in its strings.
+    ///
+    /// When the value is invalid and nothing was replaced, the file
+    /// is read again, so that the error is located in the file.
+    pub fn deserialize<T>(&self, path: &Path, mut value: Value) -> Result<T, ConfError>
+        where T: DeserializeOwned
+    {
+        let mut expanded = self.expand(&mut value)?;
+        expanded |= expand_env_vars_in(&mut value)?;
+        T::deserialize(value).map_err(|e| {
+            if !expanded {
+                if let Err(located) = SerdeFormat::read_file::<T>(path) {
+                    return located;
+                }
+            }
+            ConfError::Invalid(e)
+        })
+    }
+    /// replace the template uses in the rules of the value, in its
+    /// global rules, and in the rules of its watchers, and return
+    /// whether there was any
+    fn expand(&self, value: &mut Value) -> Result<bool, ConfError> {
+        let mut expanded = self.expand_rules(value.get_mut("rules"))?;
+        expanded |= self.expand_rules(value.get_mut("global_rules"))?;
+        if let Some(Value::Array(watchers)) = value.get_mut("watchers") {
+            for watcher in watchers {
+                expanded |= self.expand_rules(watcher.get_mut("rules"))?;
+            }
+        }
+        Ok(expanded)
+    }
+    fn expand_rules(&self, rules: Option<&mut Value>) -> Result<bool, ConfError> {
+        let rules = match rules {
+            Some(Value::Array(rules)) => rules,
+            _ => {
+                return Ok(false);
+            }
+        };
+        let mut expanded = false;
+        for rule in rules {
+            if let Value::Object(entry) = rule {
+                if entry.contains_key("use") {
+                    *rule = self.instantiate(mem::take(entry))?;
+                    expanded = true;
+                }
+            }
+        }
+        Ok(expanded)
+    }
+    /// build the rule described by a template use
+    fn instantiate(&self, mut entry: Map<String, Value>) -> Result<Value, ConfError> {
+        let name = match entry.remove("use") {
+            Some(Value::String(name)) => name,
+            other => {
+                return Err(ConfError::InvalidTemplateUse {
+                    template: other.map_or_else(String::new, |v| v.to_string()),
+                    reason: "the name of the template must be a string".to_string(),
+                });
+            }
+        };
+        let invalid = |reason: String| ConfError::InvalidTemplateUse {
+            template: name.clone(),
+            reason,
+        };
+        let template = self.templates.get(&name)
+            .ok_or_else(|| ConfError::UnknownTemplate(name.clone()))?;
+        let args = match entry.remove("with") {
+            Some(Value::Object(args)) => args,
+            None => Map::new(),
+            Some(_) => {
+                return Err(invalid("`with` must be a map".to_string()));
+            }
+        };
+        let mut rule = template.clone();
+        let mut used = HashSet::new();
+        substitute(&mut rule, &args, &mut used)
+            .map_err(|param| invalid(format!("no value for parameter {:?}", param)))?;
+        if let Some(param) = args.keys().find(|param| !used.contains(param.as_str())) {
+            return Err(invalid(format!("the template has no parameter {:?}", param)));
+        }
+        match &mut rule {
+            Value::Object(rule) => rule.extend(entry),
+            _ => {
+                return Err(invalid("the template isn't a rule".to_string()));
+            }
+        }
+        Ok(rule)
+    }
+}
+
+/// replace the parameters in the strings of the value, noting the
+/// used ones, or return the name of a parameter without value.
+///
+/// A string which is only a parameter is replaced with its value,
+/// which doesn't have to be a string.
+fn substitute<'a>(
+    value: &mut Value,
+    args: &'a Map<String, Value>,
+    used: &mut HashSet<&'a str>,
+) -> Result<(), String> {
+    match value {
+        Value::String(s) => {
+            let mut missing = None;
+            if let Some(caps) = PARAM_REGEX.captures(s) {
+                if caps[0].len() == s.len() {
+                    let param = &caps[1];
+                    let (name, arg) = args.get_key_value(param).ok_or_else(|| param.to_string())?;
+                    used.insert(name);
+                    *value = arg.clone();
+                    return Ok(());
+                }
+            }
+            let replaced = PARAM_REGEX.replace_all(s, |caps: &Captures<'_>| {
+                match args.get_key_value(&caps[1]) {
+                    Some((name, arg)) => {
+                        used.insert(name);
+                        match arg {
+                            Value::String(arg) => arg.clone(),
+                            arg => arg.to_string(),
+                        }
+                    }
+                    None => {
+                        missing.get_or_insert_with(|| caps[1].to_string());
+                        String::new()
+                    }
+                }
+            });
+            if let Some(param) = missing {
+                return Err(param);
+            }
+            *s = replaced.to_string();
+        }
+        Value::Array(values) => {
+            for value in values {
+                substitute(value, args, used)?;
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                substitute(value, args, used)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
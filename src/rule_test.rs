@@ -0,0 +1,154 @@
+use {
+    crate::*,
+    serde::Deserialize,
+    serde_json::Value,
+    std::{collections::HashMap, fmt},
+};
+
+/// An example of a task, given in a rule, with the
+/// tasks the rule must make from it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleTest {
+
+    /// the input task
+    pub task: String,
+
+    /// the results of the fetchers, by `returns` namespace
+    #[serde(default)]
+    pub fetch_results: HashMap<String, Vec<Value>>,
+
+    /// whether the rule must match the task (default: true)
+    #[serde(default = "RuleTest::default_matches")]
+    pub matches: bool,
+
+    /// the tasks the rule must make, in order
+    #[serde(default)]
+    pub expect: Vec<ExpectedTask>,
+
+}
+
+/// A task a rule must make. The target is
+/// only checked when it's given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedTask {
+    pub task: String,
+    pub queue: Option<String>,
+    pub stream: Option<String>,
+    pub zset: Option<String>,
+}
+
+impl ExpectedTask {
+    fn is_verified_by(&self, result: &RuleResult) -> bool {
+        if self.task != result.task {
+            return false;
+        }
+        match &result.target {
+            TaskTarget::Queue(queue) => {
+                self.stream.is_none() && self.zset.is_none()
+                    && self.queue.as_ref().is_none_or(|q| q == queue)
+            }
+            TaskTarget::Stream { stream, .. } => {
+                self.queue.is_none() && self.zset.is_none()
+                    && self.stream.as_ref().is_none_or(|s| s == stream)
+            }
+            TaskTarget::Zset { zset, .. } => {
+                self.queue.is_none() && self.stream.is_none()
+                    && self.zset.as_ref().is_none_or(|z| z == zset)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExpectedTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.task)?;
+        if let Some(queue) = &self.queue {
+            write!(f, " to queue {:?}", queue)?;
+        }
+        if let Some(stream) = &self.stream {
+            write!(f, " to stream {:?}", stream)?;
+        }
+        if let Some(zset) = &self.zset {
+            write!(f, " to sorted set {:?}", zset)?;
+        }
+        Ok(())
+    }
+}
+
+impl RuleTest {
+    pub fn default_matches() -> bool {
+        true
+    }
+
+    /// run the test, without Redis nor fetcher, and return
+    /// the reason of the failure, if it fails
+    pub fn run(&self, rule: &Rule) -> Result<(), String> {
+        let matches = rule.is_task_match(&self.task);
+        if matches != self.matches {
+            return Err(if matches {
+                "the rule matches the task".to_string()
+            } else {
+                "the rule doesn't match the task".to_string()
+            });
+        }
+        if !matches {
+            return Ok(());
+        }
+        let props = rule.props(&self.task).map_err(|e| format!("the rule fails: {}", e))?;
+        let mut all_fetch_results = Vec::new();
+        for fetcher in &rule.fetchers {
+            let returns = fetcher.returns();
+            let fetch_results = match self.fetch_results.get(returns) {
+                Some(values) => values.iter()
+                    .map(|value| FetchResult::from_json(returns, value))
+                    .collect(),
+                None => match fetcher.mocked_results(&props) {
+                    Some(res) => res.map_err(|e| format!("the mocked fetch fails: {}", e))?,
+                    None => Vec::new(),
+                },
+            };
+            all_fetch_results.push(fetch_results);
+        }
+        let results = rule.combine(props, all_fetch_results)
+            .map_err(|e| format!("the rule fails: {}", e))?;
+        let verified = results.len() == self.expect.len()
+            && self.expect.iter().zip(&results).all(|(e, r)| e.is_verified_by(r));
+        if verified {
+            return Ok(());
+        }
+        let expected: Vec<String> = self.expect.iter().map(|e| e.to_string()).collect();
+        let made: Vec<String> = results.iter()
+            .map(|r| format!("{:?} to {}", &r.task, &r.target))
+            .collect();
+        Err(format!(
+            "expected [{}], got [{}]",
+            expected.join(", "),
+            made.join(", "),
+        ))
+    }
+}
+
+/// run the tests of all the rules, and return the
+/// number of tests, and the failures
+pub fn run_tests(conf: &Conf) -> (usize, Vec<String>) {
+    let mut count = 0;
+    let mut failures = Vec::new();
+    for (wi, watcher) in conf.watchers.iter().enumerate() {
+        for (ri, rule) in watcher.rules.iter().enumerate() {
+            let mut rule = rule.clone();
+            rule.strict_patterns.get_or_insert(conf.strict_patterns);
+            for (ti, test) in rule.tests.iter().enumerate() {
+                count += 1;
+                if let Err(e) = test.run(&rule) {
+                    failures.push(format!(
+                        "watchers[{}].rules[{}] ({:?}).tests[{}] ({:?}): {}",
+                        wi, ri, rule.name, ti, test.task, e,
+                    ));
+                }
+            }
+        }
+    }
+    (count, failures)
+}
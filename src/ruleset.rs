@@ -5,15 +5,49 @@ use {
     serde::Deserialize,
 };
 
+/// Which of the rules matching a task are applied
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// all the matching rules
+    #[default]
+    All,
+    /// only the matching rule of highest priority
+    First,
+}
+
 /// all the rules of a watcher, that is the rules
 /// related to an input queue
 #[derive(Debug, Deserialize)]
 pub struct Ruleset {
     pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub match_mode: MatchMode,
 }
 
 impl Ruleset {
+    /// build a ruleset, with the rules sorted by decreasing
+    /// priority, rules of the same priority keeping their order
+    pub fn new(mut rules: Vec<Rule>, match_mode: MatchMode) -> Self {
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        Self { rules, match_mode }
+    }
+    /// the rules to apply to a task, in order. The rules after
+    /// a matching one with `continue: false` aren't evaluated.
+    ///
+    /// Dry run rules don't stop the evaluation, so that they
+    /// don't change what's done with the task.
     pub fn matching_rules(&self, task: &str) -> Vec<&Rule> {
-        self.rules.iter().filter(|r| r.is_match(task)).collect()
+        let mut matching = Vec::new();
+        for rule in self.rules.iter().filter(|r| r.is_match(task)) {
+            matching.push(rule);
+            if rule.dry_run {
+                continue;
+            }
+            if self.match_mode == MatchMode::First || !rule.continue_matching {
+                break;
+            }
+        }
+        matching
     }
 }
@@ -0,0 +1,570 @@
+use {
+    crate::*,
+    log::*,
+    notify::{
+        EventKind,
+        RecommendedWatcher,
+        RecursiveMode,
+        Watcher as _,
+    },
+    signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM},
+        iterator::Signals,
+    },
+    std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{self, Receiver, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// how long the configuration file must stay untouched,
+/// after a change, before it's read again
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// What the scheduler may be notified of
+#[derive(Debug)]
+pub enum SchedulerEvent {
+    /// the configuration must be read again and
+    /// the watchers restarted
+    Reload,
+    /// a watcher stopped on error
+    WatcherFailed,
+    /// the watchers known by this name must stop
+    /// taking tasks until they're resumed
+    Pause(String),
+    /// the watchers known by this name must take
+    /// tasks again
+    Resume(String),
+    /// the watchers must finish their current task
+    /// and the program end
+    Shutdown,
+}
+
+/// The scheduler starts the watchers and restarts them
+/// when the configuration changes
+pub struct Scheduler {
+    conf_path: String,
+    conf: Arc<Conf>,
+    tx: Sender<SchedulerEvent>,
+    rx: Receiver<SchedulerEvent>,
+    /// the watcher of the configuration file, when auto_reload is set
+    file_watcher: Option<RecommendedWatcher>,
+    /// the state of the watchers, for the health checks
+    health: Health,
+    /// whether the scheduler was launched with --dry-run,
+    /// which thus applies to the reloaded configurations
+    dry_run: bool,
+    /// the responses given with --mock-fetchers, which
+    /// also apply to the reloaded configurations
+    mocks: Option<Arc<FetcherMocks>>,
+    /// the watchers paused with the control channel, which
+    /// stay paused when the configuration is reloaded
+    paused: PausedWatchers,
+}
+
+/// The watchers started from a configuration
+struct RunningWatchers {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl RunningWatchers {
+    /// ask the watchers to stop after their current task
+    /// and wait for them
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for h in self.handles {
+            if h.join().is_err() {
+                warn!("a watcher thread panicked");
+            }
+        }
+    }
+}
+
+/// stop the watchers, which give back the tasks they
+/// took but didn't handle
+fn shutdown(running: RunningWatchers) {
+    info!("shutting down");
+    running.stop();
+    info!("all watchers stopped");
+}
+
+/// The slots of a watcher, and their janitors, built but not started
+pub struct PreparedWatcher {
+    janitors: Vec<Janitor>,
+    watchers: Vec<Watcher>,
+}
+
+/// build the slots of a watcher, and their janitors, connecting
+/// them to Redis, without starting them.
+///
+/// In dry run, only one slot is built, as they would all
+/// read the same tasks, and no janitor.
+pub fn prepare_watcher(
+    watcher_conf: &WatcherConf,
+    conf: &Conf,
+    paused: &PausedWatchers,
+) -> Result<PreparedWatcher, RescError> {
+    let mut janitors = Vec::new();
+    let mut watchers = Vec::new();
+    let slots = if conf.dry_run { 1 } else { watcher_conf.concurrency() };
+    for slot in 0..slots {
+        if !conf.dry_run {
+            janitors.extend(Janitor::new(watcher_conf, slot, conf)?);
+        }
+        watchers.push(Watcher::new(watcher_conf, slot, conf, paused)?);
+    }
+    Ok(PreparedWatcher { janitors, watchers })
+}
+
+impl PreparedWatcher {
+    /// start the threads of the slots and of their janitors, and
+    /// return the states of the slots for the health checks
+    pub fn start(
+        self,
+        stop: &Arc<AtomicBool>,
+        tx: &Sender<SchedulerEvent>,
+        handles: &mut Vec<JoinHandle<()>>,
+    ) -> Vec<WatcherHealth> {
+        for janitor in self.janitors {
+            let stop = Arc::clone(stop);
+            handles.push(thread::spawn(move || {
+                janitor.run(&stop);
+            }));
+        }
+        let mut health_states = Vec::new();
+        for mut watcher in self.watchers {
+            health_states.push(watcher.health_state());
+            let stop = Arc::clone(stop);
+            let tx = tx.clone();
+            handles.push(thread::spawn(move || {
+                if let Err(e) = watcher.run(&stop) {
+                    error!("watcher failed: {}", e);
+                    let _ = tx.send(SchedulerEvent::WatcherFailed);
+                }
+            }));
+        }
+        health_states
+    }
+}
+
+/// start the threads of the slots of a watcher, and of their
+/// janitors, and return the states of the slots for the health checks
+pub fn start_watcher(
+    watcher_conf: &WatcherConf,
+    conf: &Conf,
+    stop: &Arc<AtomicBool>,
+    tx: &Sender<SchedulerEvent>,
+    paused: &PausedWatchers,
+    handles: &mut Vec<JoinHandle<()>>,
+) -> Result<Vec<WatcherHealth>, RescError> {
+    Ok(prepare_watcher(watcher_conf, conf, paused)?.start(stop, tx, handles))
+}
+
+/// The threads of a configuration, built but not started, so that
+/// a configuration is applied only once all its watchers are built
+struct PreparedWatchers {
+    watchers: Vec<PreparedWatcher>,
+    discoveries: Vec<QueueDiscovery>,
+    elections: Vec<Election>,
+    mover: Option<Mover>,
+    generators: Option<Generators>,
+}
+
+impl PreparedWatchers {
+    /// start the threads, adding the watchers to the health checks
+    fn start(self, tx: &Sender<SchedulerEvent>, health: &Health) -> RunningWatchers {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handles = Vec::new();
+        let mut health_states = Vec::new();
+        for watcher in self.watchers {
+            health_states.extend(watcher.start(&stop, tx, &mut handles));
+        }
+        if let Some(mover) = self.mover {
+            let stop = Arc::clone(&stop);
+            handles.push(thread::spawn(move || {
+                mover.run(&stop);
+            }));
+        }
+        if let Some(generators) = self.generators {
+            let stop = Arc::clone(&stop);
+            handles.push(thread::spawn(move || {
+                generators.run(&stop);
+            }));
+        }
+        // the discoveries and elections add the watchers they start to the health checks
+        health.set_watchers(health_states);
+        for discovery in self.discoveries {
+            let stop = Arc::clone(&stop);
+            handles.push(thread::spawn(move || {
+                discovery.run(&stop);
+            }));
+        }
+        for election in self.elections {
+            let stop = Arc::clone(&stop);
+            handles.push(thread::spawn(move || {
+                election.run(&stop);
+            }));
+        }
+        debug!("all watchers started");
+        RunningWatchers { stop, handles }
+    }
+}
+
+impl Scheduler {
+
+    pub fn new(
+        conf_path: String,
+        conf: Conf,
+        dry_run: bool,
+        mocks: Option<Arc<FetcherMocks>>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            conf_path,
+            conf: Arc::new(conf),
+            tx,
+            rx,
+            file_watcher: None,
+            health: Health::default(),
+            dry_run,
+            mocks,
+            paused: PausedWatchers::default(),
+        }
+    }
+
+    /// run the watchers until one of them fails or
+    /// a shutdown is requested
+    pub fn run(mut self) -> Result<(), RescError> {
+        self.listen_to_signals()?;
+        if let Some(control_listener) = ControlListener::new(&self.conf, self.tx.clone()) {
+            thread::spawn(move || {
+                control_listener.run();
+            });
+        }
+        if let Some(health_conf) = &self.conf.health {
+            self.health.serve(health_conf)?;
+        }
+        // kept until the scheduler stops, so that the last traces are exported
+        let _tracing = self.conf.tracing.as_ref().map(Tracing::start).transpose()?;
+        self.update_file_watcher();
+        let mut running = self.prepare_watchers(&self.conf)?.start(&self.tx, &self.health);
+        loop {
+            match self.rx.recv() {
+                Ok(SchedulerEvent::Reload) => {
+                    match self.drain_reload_requests() {
+                        Some(SchedulerEvent::WatcherFailed) => {
+                            running.stop();
+                            return Err(RescError::WatcherFailed);
+                        }
+                        Some(SchedulerEvent::Shutdown) => {
+                            shutdown(running);
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+                    info!("reloading configuration from {}", &self.conf_path);
+                    // the running watchers are stopped only once
+                    // the new ones are all built
+                    match self.prepare_reload() {
+                        Ok((conf, prepared)) => {
+                            running.stop();
+                            debug!("all watchers stopped");
+                            self.conf = conf;
+                            self.update_file_watcher();
+                            running = prepared.start(&self.tx, &self.health);
+                        }
+                        Err(e) => {
+                            error!("Configuration not applied, keeping the previous one: {}", e);
+                        }
+                    }
+                }
+                Ok(SchedulerEvent::Shutdown) => {
+                    shutdown(running);
+                    return Ok(());
+                }
+                Ok(SchedulerEvent::Pause(name)) => {
+                    self.pause(&name);
+                }
+                Ok(SchedulerEvent::Resume(name)) => {
+                    self.resume(&name);
+                }
+                Ok(SchedulerEvent::WatcherFailed) | Err(_) => {
+                    running.stop();
+                    return Err(RescError::WatcherFailed);
+                }
+            }
+        }
+    }
+
+    /// read the configuration file again, check it, and
+    /// build its watchers
+    fn prepare_reload(&self) -> Result<(Arc<Conf>, PreparedWatchers), RescError> {
+        let mut conf = conf::read_file(&self.conf_path)?;
+        conf.dry_run |= self.dry_run;
+        if let Some(mocks) = &self.mocks {
+            conf.set_fetcher_mocks(mocks);
+        }
+        let problems = check_conf(&conf);
+        if !problems.is_empty() {
+            return Err(ConfError::Problems(problems).into());
+        }
+        let conf = Arc::new(conf);
+        let prepared = self.prepare_watchers(&conf)?;
+        Ok((conf, prepared))
+    }
+
+    fn listen_to_signals(&self) -> Result<(), RescError> {
+        let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                debug!("received signal {}", signal);
+                let event = match signal {
+                    SIGHUP => SchedulerEvent::Reload,
+                    _ => SchedulerEvent::Shutdown,
+                };
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// wait until no reload request came for a while, as editors
+    /// tend to write files in several operations, discarding the
+    /// pending reload requests and applying the pause and resume ones.
+    ///
+    /// Return the first other event received meanwhile.
+    fn drain_reload_requests(&self) -> Option<SchedulerEvent> {
+        loop {
+            match self.rx.recv_timeout(RELOAD_DEBOUNCE) {
+                Ok(SchedulerEvent::Reload) => {}
+                Ok(SchedulerEvent::Pause(name)) => self.pause(&name),
+                Ok(SchedulerEvent::Resume(name)) => self.resume(&name),
+                Ok(event) => {
+                    return Some(event);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return None;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Some(SchedulerEvent::WatcherFailed);
+                }
+            }
+        }
+    }
+
+    /// whether a watcher may be known by this name. The names of the
+    /// discovered queues can't be checked before they're found
+    fn may_know_watcher(&self, name: &str) -> bool {
+        self.conf.watchers.iter().any(|watcher| {
+            watcher.input_queue_pattern.is_some()
+                || watcher.control_names().iter().any(|n| n == name)
+        })
+    }
+
+    fn pause(&self, name: &str) {
+        if !self.may_know_watcher(name) {
+            warn!("no watcher is known as {:?}", name);
+        }
+        if self.paused.pause(name) {
+            info!("watchers known as {:?} paused", name);
+        }
+    }
+
+    fn resume(&self, name: &str) {
+        if self.paused.resume(name) {
+            info!("watchers known as {:?} resumed", name);
+        } else {
+            warn!("no watcher known as {:?} is paused", name);
+        }
+    }
+
+    /// start or stop watching the configuration file according
+    /// to the `auto_reload` setting
+    fn update_file_watcher(&mut self) {
+        if !self.conf.auto_reload {
+            self.file_watcher = None;
+            return;
+        }
+        if self.file_watcher.is_some() {
+            return;
+        }
+        match self.watch_conf_file() {
+            Ok(file_watcher) => {
+                info!("watching {} for changes", &self.conf_path);
+                self.file_watcher = Some(file_watcher);
+            }
+            Err(e) => {
+                error!("Can't watch the configuration file: {}", e);
+            }
+        }
+    }
+
+    fn watch_conf_file(&self) -> Result<RecommendedWatcher, notify::Error> {
+        // we watch the directory rather than the file because
+        // many editors replace the file instead of writing into it
+        let path = Path::new(&self.conf_path).canonicalize()?;
+        let dir = path.parent().unwrap_or(&path).to_path_buf();
+        let tx = self.tx.clone();
+        let mut file_watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            match res {
+                Ok(event) => {
+                    let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                        && event.paths.iter().any(|p| p == &path);
+                    if relevant {
+                        debug!("configuration file changed");
+                        let _ = tx.send(SchedulerEvent::Reload);
+                    }
+                }
+                Err(e) => {
+                    warn!("error while watching the configuration file: {}", e);
+                }
+            }
+        })?;
+        file_watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(file_watcher)
+    }
+
+    /// build the watchers of a configuration, the discoveries of the
+    /// queues of the watchers having a pattern, and the threads writing
+    /// delayed tasks, requeuing timed out ones, and generating tasks,
+    /// without starting them.
+    ///
+    /// With a shard, only the watchers assigned to it are built.
+    ///
+    /// With a leader election, the watchers, their discoveries, and
+    /// the generators are started by elections, only while this
+    /// instance is their leader. Those watchers are still built once
+    /// and dropped, to check they can be.
+    ///
+    /// In dry run, only the watchers and the discoveries are built,
+    /// without election.
+    fn prepare_watchers(&self, conf: &Arc<Conf>) -> Result<PreparedWatchers, RescError> {
+        let mut watchers = Vec::new();
+        let mut discoveries = Vec::new();
+        let mut elections = Vec::new();
+        let dry_run = conf.dry_run;
+        if dry_run {
+            warn!("dry run: the tasks are evaluated but nothing is written");
+        }
+        let election_conf = conf.leader_election.as_ref().filter(|_| !dry_run);
+        let shard = conf.shard;
+        if let Some(shard) = shard {
+            info!("running the watchers of shard {}", shard);
+        }
+        for watcher_conf in &conf.watchers {
+            // the queues of a pattern are assigned to shards by the discovery
+            let owned = watcher_conf.input_queue_pattern.is_some()
+                || shard.is_none_or(|shard| shard.owns(&watcher_conf.lease_name()));
+            if !owned {
+                debug!("watcher on {} left to another shard", watcher_conf.label()?);
+                continue;
+            }
+            let discovery = QueueDiscovery::new(
+                watcher_conf,
+                conf,
+                self.tx.clone(),
+                self.health.clone(),
+                self.paused.clone(),
+            );
+            if discovery.is_some() || election_conf.is_some() {
+                // started later, but checked now
+                let checked_conf = match &watcher_conf.input_queue_pattern {
+                    Some(pattern) => watcher_conf.discovered(pattern),
+                    None => watcher_conf.clone(),
+                };
+                Watcher::new(&checked_conf, 0, conf, &self.paused)?;
+            }
+            if let Some(election_conf) = election_conf {
+                let redis_conf = watcher_conf.redis.as_ref().unwrap_or(&conf.redis);
+                elections.push(Election::new(
+                    election_conf,
+                    &watcher_conf.lease_name(),
+                    format!("watcher on {}", watcher_conf.label()?),
+                    redis_conf.clone(),
+                    self.watcher_starter(watcher_conf, conf, discovery),
+                    self.tx.clone(),
+                    self.health.clone(),
+                ));
+                continue;
+            }
+            match discovery {
+                Some(discovery) => discoveries.push(discovery),
+                None => watchers.push(prepare_watcher(watcher_conf, conf, &self.paused)?),
+            }
+        }
+        let mut mover = None;
+        let mut generators = None;
+        if !dry_run {
+            mover = Mover::new(conf);
+            // the generators are run by the first shard
+            let shard_generators = Generators::new(conf)
+                .filter(|_| shard.is_none_or(|shard| shard.index == 0));
+            if let Some(shard_generators) = shard_generators {
+                match election_conf {
+                    Some(election_conf) => {
+                        let start: Starter = Box::new(move |stop, handles| {
+                            let generators = shard_generators.clone();
+                            let stop = Arc::clone(stop);
+                            handles.push(thread::spawn(move || {
+                                generators.run(&stop);
+                            }));
+                            Ok(Vec::new())
+                        });
+                        elections.push(Election::new(
+                            election_conf,
+                            "generators",
+                            "generators".to_string(),
+                            conf.redis.clone(),
+                            start,
+                            self.tx.clone(),
+                            self.health.clone(),
+                        ));
+                    }
+                    None => {
+                        generators = Some(shard_generators);
+                    }
+                }
+            }
+        }
+        Ok(PreparedWatchers { watchers, discoveries, elections, mover, generators })
+    }
+
+    /// build the function starting a watcher, or the discovery
+    /// of its queues, when this instance becomes its leader
+    fn watcher_starter(
+        &self,
+        watcher_conf: &WatcherConf,
+        conf: &Arc<Conf>,
+        discovery: Option<QueueDiscovery>,
+    ) -> Starter {
+        match discovery {
+            Some(discovery) => Box::new(move |stop, handles| {
+                let discovery = discovery.clone();
+                let stop = Arc::clone(stop);
+                handles.push(thread::spawn(move || {
+                    discovery.run(&stop);
+                }));
+                Ok(Vec::new())
+            }),
+            None => {
+                let watcher_conf = watcher_conf.clone();
+                let conf = Arc::clone(conf);
+                let tx = self.tx.clone();
+                let paused = self.paused.clone();
+                Box::new(move |stop, handles| {
+                    start_watcher(&watcher_conf, &conf, stop, &tx, &paused, handles)
+                })
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use {
     crate::*,
+    log::*,
     serde::de::DeserializeOwned,
     std::{
         fs,
@@ -14,11 +15,17 @@ pub enum SerdeFormat {
     Hjson,
     #[default]
     Json,
+    Json5,
+    Toml,
+    Yaml,
 }
 
 pub static FORMATS: &[SerdeFormat] = &[
     SerdeFormat::Hjson,
     SerdeFormat::Json,
+    SerdeFormat::Json5,
+    SerdeFormat::Toml,
+    SerdeFormat::Yaml,
 ];
 
 impl SerdeFormat {
@@ -26,12 +33,18 @@ impl SerdeFormat {
         match self {
             Self::Hjson => "hjson",
             Self::Json => "json",
+            Self::Json5 => "json5",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
         }
     }
     pub fn from_key(key: &str) -> Option<Self> {
         match key {
             "hjson" => Some(SerdeFormat::Hjson),
             "json" => Some(SerdeFormat::Json),
+            "json5" => Some(SerdeFormat::Json5),
+            "toml" => Some(SerdeFormat::Toml),
+            "yaml" | "yml" => Some(SerdeFormat::Yaml),
             _ => None,
         }
     }
@@ -46,9 +59,9 @@ impl SerdeFormat {
         where T: DeserializeOwned
     {
         let format = Self::from_path(path)?;
+        let file_content = fs::read_to_string(path)?;
         match format {
             Self::Hjson => {
-                let file_content = fs::read_to_string(path)?;
                 let conf = deser_hjson::from_str(&file_content);
                 if let Err(e) = &conf {
                     warn!("Error while deserializing conf: {:#?}", e);
@@ -56,7 +69,16 @@ impl SerdeFormat {
                 Ok(conf?)
             }
             Self::Json => {
-                Ok(serde_json::from_reader(fs::File::open(path)?)?)
+                Ok(serde_json::from_str(&file_content)?)
+            }
+            Self::Json5 => {
+                Ok(json5::from_str(&file_content)?)
+            }
+            Self::Toml => {
+                Ok(toml::from_str(&file_content)?)
+            }
+            Self::Yaml => {
+                Ok(serde_yaml::from_str(&file_content)?)
             }
         }
     }
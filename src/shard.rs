@@ -0,0 +1,50 @@
+use {
+    crate::pattern::fnv1a,
+    serde::{de, Deserialize, Deserializer},
+    std::fmt,
+};
+
+/// The part of the watchers run by this instance, among `count`
+/// instances sharing the configuration, written as "index/count"
+/// with an index starting at 0, e.g. "2/5".
+///
+/// A watcher is assigned to a shard by a hash of its input, which
+/// doesn't depend on the instance, so that all the instances agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// whether the watcher of this input is run by this shard
+    pub fn owns(&self, input: &str) -> bool {
+        fnv1a(input) % self.count == self.index
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl<'de> Deserialize<'de> for Shard {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let src = String::deserialize(deserializer)?;
+        let invalid = || de::Error::custom(format!(
+            "invalid shard {:?}: expected \"index/count\", e.g. \"0/3\"", &src,
+        ));
+        let (index, count) = src.split_once('/').ok_or_else(invalid)?;
+        let index: u64 = index.trim().parse().map_err(|_| invalid())?;
+        let count: u64 = count.trim().parse().map_err(|_| invalid())?;
+        if index >= count {
+            return Err(de::Error::custom(format!(
+                "invalid shard {:?}: the index must be less than the count", &src,
+            )));
+        }
+        Ok(Self { index, count })
+    }
+}
@@ -0,0 +1,170 @@
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+};
+
+/// The Redis command of a side effect
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SideEffectCommand {
+    /// set a field of a hash
+    Hset,
+    /// increment a counter
+    Incr,
+    /// set the expiration of a key
+    Expire,
+    /// publish a message on a channel
+    Publish,
+}
+
+/// An auxiliary write done, besides the write of the task,
+/// when a make element produces a task
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SideEffect {
+
+    pub command: SideEffectCommand,
+
+    /// the key of the hash, counter, or expiring key
+    pub key: Option<Pattern>,
+
+    /// the field of the hash, for hset
+    pub field: Option<Pattern>,
+
+    /// the value of the field, for hset
+    pub value: Option<Pattern>,
+
+    /// the increment, for incr (default: 1)
+    pub by: Option<i64>,
+
+    /// the duration, in seconds, before the key expires, for expire
+    pub ttl: Option<u64>,
+
+    /// the channel, for publish
+    pub channel: Option<Pattern>,
+
+    /// the published message, for publish
+    pub message: Option<Pattern>,
+
+}
+
+/// A side effect, with its patterns injected
+///
+/// It's serialized with the delayed task it belongs to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Hset {
+        key: String,
+        field: String,
+        value: String,
+    },
+    Incr {
+        key: String,
+        by: i64,
+    },
+    Expire {
+        key: String,
+        ttl: u64,
+    },
+    Publish {
+        channel: String,
+        message: String,
+    },
+}
+
+impl SideEffect {
+    /// check the side effect has the properties of its
+    /// command, and only them
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let keyed = self.key.is_some() && self.channel.is_none() && self.message.is_none();
+        let no_field = self.field.is_none() && self.value.is_none();
+        let valid = match self.command {
+            SideEffectCommand::Hset => {
+                keyed && self.field.is_some() && self.value.is_some()
+                    && self.by.is_none() && self.ttl.is_none()
+            }
+            SideEffectCommand::Incr => keyed && no_field && self.ttl.is_none(),
+            SideEffectCommand::Expire => keyed && no_field && self.by.is_none() && self.ttl.is_some(),
+            SideEffectCommand::Publish => {
+                self.key.is_none() && no_field && self.by.is_none() && self.ttl.is_none()
+                    && self.channel.is_some() && self.message.is_some()
+            }
+        };
+        if !valid {
+            return Err(match self.command {
+                SideEffectCommand::Hset => "a hset effect needs a key, a field, and a value",
+                SideEffectCommand::Incr => "an incr effect needs a key, and optionally by",
+                SideEffectCommand::Expire => "an expire effect needs a key and a ttl",
+                SideEffectCommand::Publish => "a publish effect needs a channel and a message",
+            });
+        }
+        Ok(())
+    }
+    /// the patterns of the side effect, with their names
+    pub fn patterns(&self) -> Vec<(&'static str, &Pattern)> {
+        [
+            ("key", &self.key),
+            ("field", &self.field),
+            ("value", &self.value),
+            ("channel", &self.channel),
+            ("message", &self.message),
+        ]
+            .iter()
+            .filter_map(|&(name, pattern)| pattern.as_ref().map(|pattern| (name, pattern)))
+            .collect()
+    }
+    /// build the effect, the patterns being injected
+    /// with the given function
+    pub fn effect(
+        &self,
+        inject: impl Fn(&Pattern) -> Result<String, RescError>,
+    ) -> Result<Effect, RescError> {
+        // the presence of the patterns is checked on deserialization
+        let get = |pattern: &Option<Pattern>| inject(pattern.as_ref().unwrap());
+        Ok(match self.command {
+            SideEffectCommand::Hset => Effect::Hset {
+                key: get(&self.key)?,
+                field: get(&self.field)?,
+                value: get(&self.value)?,
+            },
+            SideEffectCommand::Incr => Effect::Incr {
+                key: get(&self.key)?,
+                by: self.by.unwrap_or(1),
+            },
+            SideEffectCommand::Expire => Effect::Expire {
+                key: get(&self.key)?,
+                ttl: self.ttl.unwrap(),
+            },
+            SideEffectCommand::Publish => Effect::Publish {
+                channel: get(&self.channel)?,
+                message: get(&self.message)?,
+            },
+        })
+    }
+}
+
+impl Effect {
+    /// the Redis command of the effect, with its arguments
+    pub fn command(&self) -> Vec<String> {
+        match self {
+            Self::Hset { key, field, value } => {
+                vec!["HSET".to_string(), key.clone(), field.clone(), value.clone()]
+            }
+            Self::Incr { key, by } => {
+                vec!["INCRBY".to_string(), key.clone(), by.to_string()]
+            }
+            Self::Expire { key, ttl } => {
+                vec!["EXPIRE".to_string(), key.clone(), ttl.to_string()]
+            }
+            Self::Publish { channel, message } => {
+                vec!["PUBLISH".to_string(), channel.clone(), message.clone()]
+            }
+        }
+    }
+    /// add the command of the effect to a pipeline
+    pub fn add_to(&self, pipe: &mut redis::Pipeline) {
+        let command = self.command();
+        pipe.cmd(&command[0]).arg(&command[1..]).ignore();
+    }
+}
@@ -0,0 +1,183 @@
+use {
+    crate::*,
+    serde::Serialize,
+    std::{fs, io, path::Path},
+};
+
+/// The outcome of the simulated handling of tasks
+#[derive(Debug, Default, Serialize)]
+pub struct SimulationReport {
+    /// the number of input tasks
+    pub tasks: usize,
+    /// the number of input tasks no rule matched
+    pub unmatched: usize,
+    /// the number of tasks the rules made
+    pub made: usize,
+    /// the number of failed applications of rules
+    pub failures: usize,
+    pub inputs: Vec<SimulatedInput>,
+}
+
+/// An input task and the rules matching it
+#[derive(Debug, Serialize)]
+pub struct SimulatedInput {
+    pub task: String,
+    pub rules: Vec<SimulatedRule>,
+}
+
+/// A rule applied to an input task
+#[derive(Debug, Serialize)]
+pub struct SimulatedRule {
+    /// the input of the watcher of the rule
+    pub watcher: String,
+    pub rule: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    pub made: Vec<SimulatedTask>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A task made by a rule, with where it would be written
+#[derive(Debug, Serialize)]
+pub struct SimulatedTask {
+    pub task: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zset: Option<String>,
+    /// the name of the Redis server, when not the main one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redis: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<f64>,
+}
+
+impl From<RuleResult> for SimulatedTask {
+    fn from(r: RuleResult) -> Self {
+        let (queue, stream, zset) = match r.target {
+            TaskTarget::Queue(queue) => (Some(queue), None, None),
+            TaskTarget::Stream { stream, .. } => (None, Some(stream), None),
+            TaskTarget::Zset { zset, .. } => (None, None, Some(zset)),
+        };
+        Self {
+            task: r.task,
+            queue,
+            stream,
+            zset,
+            redis: r.redis,
+            delay: r.delay,
+        }
+    }
+}
+
+impl SimulationReport {
+    /// print the tasks made from every input task, then the totals
+    pub fn print(&self) {
+        for input in &self.inputs {
+            println!("{}", &input.task);
+            if input.rules.iter().all(|rule| rule.dry_run) {
+                println!("  no matching rule");
+            }
+            for rule in &input.rules {
+                let dry_run = if rule.dry_run { " (dry run)" } else { "" };
+                if let Some(error) = &rule.error {
+                    println!("  rule {:?}{} fails: {}", &rule.rule, dry_run, error);
+                    continue;
+                }
+                if rule.made.is_empty() {
+                    println!("  rule {:?}{} makes no task", &rule.rule, dry_run);
+                }
+                for made in &rule.made {
+                    let target = made.queue.as_ref().map(|q| format!("queue {:?}", q))
+                        .or_else(|| made.stream.as_ref().map(|s| format!("stream {:?}", s)))
+                        .or_else(|| made.zset.as_ref().map(|z| format!("sorted set {:?}", z)))
+                        .unwrap_or_default();
+                    println!("  rule {:?}{} -> {:?} to {}", &rule.rule, dry_run, &made.task, target);
+                }
+            }
+        }
+        self.print_totals();
+    }
+    pub fn print_totals(&self) {
+        println!(
+            "{} task(s), {} unmatched, {} task(s) made, {} failure(s)",
+            self.tasks, self.unmatched, self.made, self.failures,
+        );
+    }
+    /// write the report as JSON
+    pub fn write_file(&self, path: &Path) -> io::Result<()> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// read the tasks of a file, one per line, ignoring the empty lines
+pub fn read_tasks(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Feed the tasks to the rules of all the watchers, without Redis,
+/// and tell what the rules make from them.
+///
+/// The fetchers are called, unless mocked, except the redis ones
+/// which make the rule fail. Debouncing is ignored.
+pub fn simulate(conf: &Conf, tasks: Vec<String>) -> SimulationReport {
+    let mut rulesets = Vec::new();
+    for watcher in &conf.watchers {
+        let name = watcher.label()
+            .unwrap_or_else(|_| "<invalid input>".to_string());
+        let mut rules = watcher.rules.clone();
+        for rule in &mut rules {
+            rule.strict_patterns.get_or_insert(conf.strict_patterns);
+        }
+        rulesets.push((name, Ruleset::new(rules, watcher.match_mode)));
+    }
+    let mut report = SimulationReport::default();
+    for task in tasks {
+        let mut rules = Vec::new();
+        for (watcher, ruleset) in &rulesets {
+            for rule in ruleset.matching_rules(&task) {
+                let (made, error) = match simulate_rule(rule, &task) {
+                    Ok(results) => (results.into_iter().map(SimulatedTask::from).collect(), None),
+                    Err(e) => (Vec::new(), Some(e)),
+                };
+                rules.push(SimulatedRule {
+                    watcher: watcher.clone(),
+                    rule: rule.name.clone(),
+                    dry_run: rule.dry_run,
+                    made,
+                    error,
+                });
+            }
+        }
+        report.tasks += 1;
+        if rules.iter().all(|rule| rule.dry_run) {
+            report.unmatched += 1;
+        }
+        report.made += rules.iter().map(|rule| rule.made.len()).sum::<usize>();
+        report.failures += rules.iter().filter(|rule| rule.error.is_some()).count();
+        report.inputs.push(SimulatedInput { task, rules });
+    }
+    report
+}
+
+fn simulate_rule(rule: &Rule, task: &str) -> Result<Vec<RuleResult>, String> {
+    let props = rule.props(task).map_err(|e| e.to_string())?;
+    let mut all_fetch_results = Vec::new();
+    for fetcher in &rule.fetchers {
+        match fetcher.standalone_results(&props) {
+            Some(res) => all_fetch_results.push(res.map_err(|e| format!("fetch failed: {}", e))?),
+            None => return Err(format!("the fetcher of {:?} needs Redis", fetcher.returns())),
+        }
+    }
+    rule.combine(props, all_fetch_results).map_err(|e| e.to_string())
+}
@@ -0,0 +1,45 @@
+use {
+    serde::Deserialize,
+    serde_json::Value,
+    std::collections::HashMap,
+};
+
+/// How a rule reads the input tasks
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskFormat {
+    /// the task is a string, only read with the `on` regex
+    #[default]
+    Text,
+    /// the task is a JSON document whose values are also
+    /// available in the `payload` namespace
+    Json,
+}
+
+/// add to the properties the string, number, and boolean values of
+/// a JSON value, named by their path from `name` with dots, like
+/// `payload.customer.id` or `payload.items.0.sku`
+pub fn insert_json_props(name: String, value: &Value, props: &mut HashMap<String, String>) {
+    match value {
+        Value::String(s) => {
+            props.insert(name, s.clone());
+        }
+        Value::Number(n) => {
+            props.insert(name, n.to_string());
+        }
+        Value::Bool(b) => {
+            props.insert(name, b.to_string());
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                insert_json_props(format!("{}.{}", name, i), value, props);
+            }
+        }
+        Value::Object(object) => {
+            for (key, value) in object {
+                insert_json_props(format!("{}.{}", name, key), value, props);
+            }
+        }
+        Value::Null => {}
+    }
+}
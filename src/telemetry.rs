@@ -0,0 +1,134 @@
+use {
+    crate::*,
+    serde::Deserialize,
+    std::fmt,
+};
+#[cfg(feature = "otlp")]
+use {
+    log::*,
+    opentelemetry::{
+        global,
+        trace::{Status, TraceContextExt, Tracer as _},
+        Context,
+        KeyValue,
+    },
+};
+
+/// Configuration of the export of traces, with OTLP over HTTP
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConf {
+
+    /// the url of the OTLP collector, e.g.
+    /// "http://127.0.0.1:4318/v1/traces"
+    pub otlp_endpoint: String,
+
+    /// the name of the service, in the traces
+    #[serde(default = "TracingConf::default_service_name")]
+    pub service_name: String,
+
+}
+
+impl TracingConf {
+    pub fn default_service_name() -> String {
+        "resc".to_string()
+    }
+}
+
+/// The exporter of traces, flushed and stopped when dropped
+pub struct Tracing {
+    #[cfg(feature = "otlp")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+impl Tracing {
+    /// start exporting the spans, which are ignored until then
+    #[cfg(feature = "otlp")]
+    pub fn start(conf: &TracingConf) -> Result<Self, RescError> {
+        use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+        use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&conf.otlp_endpoint)
+            .build()
+            .map_err(|e| RescError::Tracing(e.to_string()))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(conf.service_name.clone()).build())
+            .build();
+        global::set_tracer_provider(provider.clone());
+        info!("traces exported to {}", &conf.otlp_endpoint);
+        Ok(Self { provider })
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    pub fn start(_conf: &TracingConf) -> Result<Self, RescError> {
+        Err(RescError::Tracing("resc was compiled without the otlp feature".to_string()))
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("traces not completely exported: {}", e);
+        }
+    }
+}
+
+/// A span of a trace, ended when dropped.
+///
+/// It does nothing when resc is compiled without the
+/// otlp feature or when tracing isn't configured.
+pub struct TraceSpan {
+    #[cfg(feature = "otlp")]
+    cx: Context,
+}
+
+impl TraceSpan {
+    /// start a new trace
+    pub fn root(name: &'static str) -> Self {
+        Self::start(name, None)
+    }
+
+    /// start a span inside this one
+    pub fn child(&self, name: &'static str) -> Self {
+        Self::start(name, Some(self))
+    }
+
+    #[cfg(feature = "otlp")]
+    fn start(name: &'static str, parent: Option<&Self>) -> Self {
+        let parent_cx = parent.map_or_else(Context::new, |parent| parent.cx.clone());
+        let span = global::tracer("resc").start_with_context(name, &parent_cx);
+        Self { cx: parent_cx.with_span(span) }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn start(_name: &'static str, _parent: Option<&Self>) -> Self {
+        Self {}
+    }
+
+    #[cfg(feature = "otlp")]
+    pub fn set_attribute(&self, key: &'static str, value: &str) {
+        self.cx.span().set_attribute(KeyValue::new(key, value.to_string()));
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    pub fn set_attribute(&self, _key: &'static str, _value: &str) {}
+
+    /// mark the span as failed
+    #[cfg(feature = "otlp")]
+    pub fn set_error(&self, error: &dyn fmt::Display) {
+        self.cx.span().set_status(Status::error(error.to_string()));
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    pub fn set_error(&self, _error: &dyn fmt::Display) {}
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for TraceSpan {
+    fn drop(&mut self) {
+        self.cx.span().end();
+    }
+}
@@ -0,0 +1,111 @@
+use {
+    chrono::{DateTime, Datelike, Local, NaiveTime, Timelike, Weekday},
+    cron::Schedule,
+    serde::{de, Deserialize, Deserializer},
+    std::str::FromStr,
+};
+
+/// When a rule is active, in local time. All the given
+/// criteria must be verified.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindow {
+
+    /// a range of hours, like "22:00-06:00", which
+    /// may span midnight
+    pub hours: Option<HourRange>,
+
+    /// the days of the week, like ["sat", "sun"]
+    #[serde(default, deserialize_with = "deserialize_weekdays")]
+    pub weekdays: Vec<Weekday>,
+
+    /// a cron expression, like "* 22-23,0-5 * * *", the
+    /// window being made of the minutes it matches
+    #[serde(default, deserialize_with = "deserialize_minutes")]
+    pub cron: Option<Schedule>,
+
+}
+
+/// A range of times of the day, the end being excluded
+#[derive(Debug, Clone, Copy)]
+pub struct HourRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: DateTime<Local>) -> bool {
+        if let Some(hours) = &self.hours {
+            if !hours.contains(time.time()) {
+                return false;
+            }
+        }
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&time.weekday()) {
+            return false;
+        }
+        if let Some(cron) = &self.cron {
+            if !cron.includes(time.with_second(0).unwrap_or(time)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl HourRange {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // the range spans midnight
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for HourRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid hours {:?}, expected <HH:MM>-<HH:MM>", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").map_err(|_| invalid())?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").map_err(|_| invalid())?;
+        Ok(Self { start, end })
+    }
+}
+
+impl<'de> Deserialize<'de> for HourRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+fn deserialize_weekdays<'de, D>(deserializer: D) -> Result<Vec<Weekday>, D::Error>
+    where D: Deserializer<'de>
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            s.parse::<Weekday>()
+                .map_err(|_| de::Error::custom(format!("invalid weekday {:?}", s)))
+        })
+        .collect()
+}
+
+fn deserialize_minutes<'de, D>(deserializer: D) -> Result<Option<Schedule>, D::Error>
+    where D: Deserializer<'de>
+{
+    let src = String::deserialize(deserializer)?;
+    // the cron crate wants the seconds, and the whole minutes are matched
+    let expr = if src.split_whitespace().count() == 5 {
+        format!("0 {}", src)
+    } else {
+        src.clone()
+    };
+    Schedule::from_str(&expr)
+        .map(Some)
+        .map_err(|e| de::Error::custom(format!("invalid cron expression {:?}: {}", src, e)))
+}
@@ -0,0 +1,100 @@
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::*,
+    regex::Regex,
+    rhai::{Dynamic, Engine, Map, Scope, AST, INT},
+    serde::{de, Deserialize, Deserializer},
+    std::{collections::HashMap, fmt},
+};
+
+/// the maximal number of operations of a run of a script
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// the maximal length of a string built by a script
+const MAX_STRING_SIZE: usize = 1_000_000;
+
+lazy_static! {
+    static ref ENGINE: Engine = {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        engine.on_print(|s| debug!("transform script: {}", s));
+        engine.on_debug(|s, _, _| debug!("transform script: {}", s));
+        engine.register_fn("pad_left", pad_left);
+        engine
+    };
+    static ref ASSIGNMENT: Regex = Regex::new(
+        r#"\bprops\s*(?:\.\s*(\w+)|\[\s*"([^"]+)"\s*\])\s*=[^=]"#
+    ).unwrap();
+}
+
+/// pad a string on the left to the given length,
+/// e.g. `pad_left("42", 5, '0')` is "00042"
+fn pad_left(s: &str, len: INT, pad: char) -> String {
+    let missing = (len.max(0) as usize).saturating_sub(s.chars().count());
+    let mut padded: String = std::iter::repeat_n(pad, missing).collect();
+    padded.push_str(s);
+    padded
+}
+
+/// A Rhai script computing derived properties, run on the
+/// properties of the task after the captures and fetches, and
+/// before the make patterns are valued.
+///
+/// The script reads and writes the properties in the `props` map,
+/// e.g. `props.bucket = parse_int(props.id) / 1000`. Values which
+/// aren't strings are converted, and a property set to `()` is removed.
+#[derive(Clone)]
+pub struct Transform {
+    pub src: String,
+    ast: AST,
+}
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transform").field("src", &self.src).finish()
+    }
+}
+
+impl Transform {
+    pub fn new(src: &str) -> Result<Self, ConfError> {
+        let ast = ENGINE.compile(src)
+            .map_err(|e| ConfError::InvalidTransform(e.to_string()))?;
+        Ok(Self { src: src.to_string(), ast })
+    }
+    /// the names of the properties the script assigns
+    pub fn assigned_properties(&self) -> Vec<&str> {
+        ASSIGNMENT.captures_iter(&self.src)
+            .filter_map(|c| c.get(1).or_else(|| c.get(2)))
+            .map(|m| m.as_str())
+            .collect()
+    }
+    /// run the script and return the transformed properties
+    pub fn apply(&self, props: &HashMap<String, String>) -> RescResult<HashMap<String, String>> {
+        let map: Map = props.iter()
+            .map(|(key, value)| (key.into(), Dynamic::from(value.clone())))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push("props", map);
+        ENGINE.run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| RescError::Transform(e.to_string()))?;
+        let map: Map = scope.get_value("props")
+            .ok_or_else(|| RescError::Transform("props isn't a map anymore".to_string()))?;
+        Ok(map.into_iter()
+            .filter(|(_, value)| !value.is_unit())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let src = String::deserialize(deserializer)?;
+        Self::new(&src).map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,166 @@
+use {
+    crate::*,
+    serde::{de, Deserialize, Deserializer},
+    serde_json::Value,
+    std::{collections::HashMap, fmt, path::PathBuf},
+};
+
+#[cfg(feature = "wasm")]
+use {
+    lazy_static::lazy_static,
+    std::convert::TryFrom,
+    wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder},
+};
+
+/// the fuel given to a call of a plugin, roughly
+/// its maximal number of instructions
+#[cfg(feature = "wasm")]
+const WASM_FUEL: u64 = 1_000_000_000;
+
+/// the maximal size of the memory of a plugin, in bytes
+#[cfg(feature = "wasm")]
+const WASM_MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+#[cfg(feature = "wasm")]
+lazy_static! {
+    static ref WASM_ENGINE: Engine = {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config).unwrap() // the config is valid
+    };
+}
+
+/// A WebAssembly module, loaded from the file whose path
+/// is given in the configuration.
+///
+/// The module gets no import, and so has no access to the
+/// system, and each call runs in a new instance, with limited
+/// fuel and memory. It must export its `memory`, an
+/// `alloc(len: i32) -> i32` function giving the place of an
+/// input of `len` bytes, and the functions called by resc, which
+/// take the pointer and length of their input and return the
+/// pointer and length of their output packed in an i64, the
+/// pointer in the high 32 bits.
+///
+/// It's only available when resc is compiled with the `wasm` feature.
+#[derive(Clone)]
+pub struct WasmPlugin {
+    pub path: PathBuf,
+    #[cfg(feature = "wasm")]
+    module: Module,
+}
+
+impl fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmPlugin").field("path", &self.path).finish()
+    }
+}
+
+impl WasmPlugin {
+    #[cfg(feature = "wasm")]
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let module = Module::from_file(&WASM_ENGINE, &path)
+            .map_err(|e| format!("invalid wasm module {:?}: {}", &path, e))?;
+        Ok(Self { path, module })
+    }
+    #[cfg(not(feature = "wasm"))]
+    pub fn load(_path: PathBuf) -> Result<Self, String> {
+        Err("wasm plugins need resc to be compiled with the wasm feature".to_string())
+    }
+
+    /// call an exported function of the module with the input,
+    /// in a new sandboxed instance, and return its output
+    #[cfg(feature = "wasm")]
+    pub fn call(&self, function: &str, input: &[u8]) -> Result<Vec<u8>, RescError> {
+        let wasm_err = |e: wasmtime::Error| RescError::Wasm {
+            path: self.path.clone(),
+            reason: format!("{}: {}", function, e.root_cause()),
+        };
+        let limits = StoreLimitsBuilder::new().memory_size(WASM_MAX_MEMORY).build();
+        let mut store: Store<StoreLimits> = Store::new(&WASM_ENGINE, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(WASM_FUEL).map_err(wasm_err)?;
+        let instance = Instance::new(&mut store, &self.module, &[]).map_err(wasm_err)?;
+        let memory = instance.get_memory(&mut store, "memory")
+            .ok_or_else(|| wasm_err(wasmtime::Error::msg("no exported memory")))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(wasm_err)?;
+        let func = instance.get_typed_func::<(i32, i32), i64>(&mut store, function)
+            .map_err(wasm_err)?;
+        let len = i32::try_from(input.len())
+            .map_err(|_| wasm_err(wasmtime::Error::msg("input too long")))?;
+        let ptr = alloc.call(&mut store, len).map_err(wasm_err)?;
+        memory.write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| wasm_err(e.into()))?;
+        let packed = func.call(&mut store, (ptr, len)).map_err(wasm_err)?;
+        let out_ptr = (packed >> 32) as u32 as usize;
+        let out_len = packed as u32 as usize;
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output)
+            .map_err(|e| wasm_err(e.into()))?;
+        Ok(output)
+    }
+    #[cfg(not(feature = "wasm"))]
+    pub fn call(&self, _function: &str, _input: &[u8]) -> Result<Vec<u8>, RescError> {
+        unreachable!() // no plugin can be loaded
+    }
+
+    /// transform a task made by a rule, with the `transform`
+    /// function of the module. None is returned when the output
+    /// is empty, the task being then dropped.
+    pub fn transform(&self, task: String) -> Result<Option<String>, RescError> {
+        let output = self.call("transform", task.as_bytes())?;
+        if output.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(output)
+            .map(Some)
+            .map_err(|_| RescError::Wasm {
+                path: self.path.clone(),
+                reason: "the transformed task isn't UTF-8".to_string(),
+            })
+    }
+}
+
+impl<'de> Deserialize<'de> for WasmPlugin {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let path = PathBuf::deserialize(deserializer)?;
+        Self::load(path).map_err(de::Error::custom)
+    }
+}
+
+/// A fetcher calling the `fetch` function of a WebAssembly
+/// module, with the properties as a JSON object. The output is
+/// a JSON object or an array of JSON objects or values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WasmFetcher {
+    pub returns: String,
+
+    /// the path of the module
+    pub module: WasmPlugin,
+}
+
+impl Fetch for WasmFetcher {
+    fn returns(&self) -> &str {
+        &self.returns
+    }
+    fn results(&self, props: &HashMap<String, String>) -> RescResult<Vec<FetchResult>> {
+        let input = serde_json::to_vec(props).unwrap(); // a map of strings can't fail
+        let output = self.module.call("fetch", &input)?;
+        let value: Value = serde_json::from_slice(&output)
+            .map_err(|e| RescError::Wasm {
+                path: self.module.path.clone(),
+                reason: format!("the output of fetch isn't JSON: {}", e),
+            })?;
+        let results = match &value {
+            Value::Array(values) => values.iter()
+                .map(|value| FetchResult::from_json(&self.returns, value))
+                .collect(),
+            _ => vec![FetchResult::from_json(&self.returns, &value)],
+        };
+        Ok(results)
+    }
+}
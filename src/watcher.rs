@@ -1,169 +1,1115 @@
 use {
     crate::*,
+    lazy_static::lazy_static,
     log::*,
-    redis::{self, Commands, Connection},
+    redis::{self, Commands},
     serde::Deserialize,
     std::{
-        time::SystemTime,
+        collections::HashMap,
+        mem,
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant, SystemTime},
     },
 };
 
-#[derive(Debug, Deserialize)]
+/// how long, in seconds, a watcher waits by default for an event
+/// before checking whether it's been asked to stop
+const DEFAULT_POP_TIMEOUT: usize = 1;
+
+/// how often a paused watcher checks whether it's resumed
+const PAUSE_CHECK_PERIOD: Duration = Duration::from_millis(200);
+
+/// the bounds of the delay before a reconnection attempt
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// A script writing a task whose write depends on the state of the
+/// server, so that the checks and the write are atomic: concurrent
+/// watchers can't both write the same task, exceed a rate limit, or
+/// push a task to a queue which is already full.
+///
+/// A duplicate task isn't written. A delayed task is written to the
+/// delayed set, as is a task whose queue already holds `max_queue_len`
+/// tasks: it's parked there until the mover can push it.
+///
+/// The rate limit is a token bucket, implemented as a "generic cell
+/// rate algorithm": the bucket key holds the time at which the bucket
+/// will be full again. Each task takes a token, and is deferred to the
+/// time the token is available when the bucket is empty.
+///
+/// KEYS[1] is the queue, stream, or sorted set, KEYS[2] the delayed set,
+/// KEYS[3] the task set or the dedup key, and KEYS[4] the bucket of the
+/// rate limit (KEYS[3] and KEYS[4] are the target when unused). ARGV[1]
+/// is the task, ARGV[2] the time, ARGV[3] the due time ("" when the task
+/// isn't delayed), ARGV[4] the member in the delayed set, ARGV[5] the kind
+/// of deduplication ("set", "key", or "" for none), ARGV[6] its ttl in
+/// seconds (0 for none), ARGV[7] the maximal length of the queue ("" for
+/// none), ARGV[8] the interval between two tokens ("" for no rate limit),
+/// ARGV[9] the time to fill the whole bucket, and from ARGV[10] the write,
+/// with the side effects, as encoded by `write_args`. The effects of a task
+/// which isn't directly written are in its member of the delayed set, and
+/// done when the mover writes it.
+///
+/// The script returns the outcome, as read by `WriteOutcome::from_script`,
+/// and the delay of the task.
+const WRITE_SCRIPT_BODY: &str = r"
+    local now = tonumber(ARGV[2])
+    local due = tonumber(ARGV[3])
+    local ttl = tonumber(ARGV[6])
+    if ARGV[5] == 'key' then
+        local added
+        if ttl > 0 then
+            added = redis.call('SET', KEYS[3], ARGV[1], 'NX', 'EX', ttl)
+        else
+            added = redis.call('SET', KEYS[3], ARGV[1], 'NX')
+        end
+        if not added then
+            return {0, '0'}
+        end
+    elseif ARGV[5] == 'set' then
+        if ttl > 0 then
+            redis.call('ZREMRANGEBYSCORE', KEYS[3], '-inf', now - ttl)
+        end
+        if redis.call('ZSCORE', KEYS[3], ARGV[1]) then
+            return {0, '0'}
+        end
+        redis.call('ZADD', KEYS[3], now, ARGV[1])
+    end
+    local limited = false
+    if ARGV[8] ~= '' then
+        local t = due or now
+        local interval = tonumber(ARGV[8])
+        local full_at = tonumber(redis.call('GET', KEYS[4])) or t
+        if full_at < t then
+            full_at = t
+        end
+        local wait = full_at + interval - tonumber(ARGV[9]) - t
+        full_at = full_at + interval
+        redis.call('SET', KEYS[4], full_at, 'EX', math.ceil(full_at - now))
+        if wait > 0 then
+            due = t + wait
+            limited = true
+        end
+    end
+    if not due and ARGV[7] ~= '' and redis.call('LLEN', KEYS[1]) >= tonumber(ARGV[7]) then
+        redis.call('ZADD', KEYS[2], now, ARGV[4])
+        return {3, '0'}
+    end
+    if due then
+        redis.call('ZADD', KEYS[2], due, ARGV[4])
+        if limited then
+            return {4, tostring(due - now)}
+        end
+        return {2, tostring(due - now)}
+    end
+    write(KEYS[1], ARGV[1], 10)
+    return {1, '0'}
+";
+
+lazy_static! {
+    static ref WRITE_SCRIPT: String = format!("{}{}", WRITE_FUNCTION, WRITE_SCRIPT_BODY);
+}
+
+/// the bounds of the delay before a new attempt of a failed rule
+const RETRY_MIN_DELAY: Duration = Duration::from_millis(200);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct WatcherConf {
-    pub input_queue: String,
+    /// when false, the watcher is ignored
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// the name of the watcher in the commands of the
+    /// control channel, besides its input
+    pub name: Option<String>,
+    /// the Redis server of this watcher, when not the global one
+    pub redis: Option<RedisConf>,
+    pub input_queue: Option<String>,
+    /// several input queues sharing the rules, each one being
+    /// read as if it were the input queue of its own watcher
+    #[serde(default)]
+    pub input_queues: Vec<String>,
+    /// a glob pattern of the input queues, which are looked for
+    /// periodically, each one found being read as if it were the
+    /// input queue of its own watcher
+    pub input_queue_pattern: Option<String>,
+    /// how often, in seconds, the queues matching the
+    /// input_queue_pattern are looked for (default: 10)
+    pub discovery_interval: Option<u64>,
     pub taken_queue: Option<String>,
+    /// the stream to read, instead of an input queue
+    pub input_stream: Option<String>,
+    /// the pattern of the channels to subscribe to, instead
+    /// of reading an input queue
+    pub input_channel: Option<String>,
+    /// the keyspace notifications to handle, instead
+    /// of reading an input queue
+    pub input_keyspace: Option<KeyspaceConf>,
+    /// the consumer group, when reading a stream (default: "resc")
+    pub consumer_group: Option<String>,
+    /// the consumer name, when reading a stream (default: "resc")
+    pub consumer_name: Option<String>,
+    /// the field holding the task in the stream entries (default: "task")
+    pub stream_field: Option<String>,
+    /// how long, in seconds, the watcher blocks waiting for an event
+    /// before checking whether it must stop (default: 1). It can't
+    /// be 0, which would mean blocking indefinitely
+    pub pop_timeout: Option<NonZeroUsize>,
+    /// the queue where the tasks matched by no rule are pushed
+    pub unmatched_queue: Option<String>,
+    /// when set, a counter of the unmatched tasks is kept in
+    /// "{unmatched_queue}/count" and expires after this many
+    /// seconds without unmatched task
+    pub unmatched_counter_ttl: Option<usize>,
+    /// when set, the times the tasks are taken are tracked, and only
+    /// the tasks of the taken queue taken more than this many seconds
+    /// ago are moved back to the input queue on startup
+    pub stale_taken_age: Option<f64>,
+    /// when set with `stale_taken_age`, the stale tasks of the taken
+    /// queue are also looked for every this many seconds
+    pub stale_taken_check_interval: Option<u64>,
+    /// when set, a janitor moves back to the input queue the tasks
+    /// not acknowledged this many seconds after being taken
+    pub taken_timeout: Option<f64>,
+    /// whether all the matching rules are applied, or only the
+    /// one of highest priority
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// the number of events handled at the same time, each
+    /// one by its own thread and connection (default: 1)
+    pub concurrency: Option<NonZeroUsize>,
+    /// when set, at most this many events are taken from the
+    /// input per second, shared by the `concurrency` slots
+    pub max_tasks_per_second: Option<f64>,
+    /// paths of files whose rules are added to this watcher
+    #[serde(default)]
+    pub include: Vec<std::path::PathBuf>,
+    #[serde(default)]
     pub rules: Vec<Rule>,
 }
 
-/// A watcher watches the events incoming in one specific queue
-/// and applies rules to generate tasks
+/// The keyspace notifications a watcher handles, the
+/// task being the key
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyspaceConf {
+    /// the pattern of the keys
+    pub keys: String,
+    /// the events to handle, e.g. "expired" or "set".
+    /// All events are handled when none is given.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// the database, when not the one of the connection
+    pub db: Option<i64>,
+}
+
+impl KeyspaceConf {
+    pub fn accepts(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+impl WatcherConf {
+    /// the number of events handled at the same time
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.map_or(1, NonZeroUsize::get)
+    }
+
+    /// the minimal delay between two events taken by a slot,
+    /// to honor `max_tasks_per_second`
+    pub fn slot_pop_interval(&self) -> Option<Duration> {
+        self.max_tasks_per_second
+            .filter(|max| *max > 0.0 && max.is_finite())
+            .map(|max| Duration::from_secs_f64(self.concurrency() as f64 / max))
+    }
+
+    /// the watchers reading each one of the input queues,
+    /// or just this watcher when there's no `input_queues`
+    pub fn split_input_queues(mut self) -> Result<Vec<Self>, ConfError> {
+        if self.input_queues.is_empty() {
+            return Ok(vec![self]);
+        }
+        let other_inputs = [
+            self.input_queue.is_some(),
+            self.input_queue_pattern.is_some(),
+            self.input_stream.is_some(),
+            self.input_channel.is_some(),
+            self.input_keyspace.is_some(),
+        ];
+        if other_inputs.contains(&true) {
+            return Err(ConfError::InvalidWatcherInput);
+        }
+        if self.taken_queue.is_some() {
+            return Err(ConfError::SharedTakenQueue);
+        }
+        let queues = mem::take(&mut self.input_queues);
+        Ok(queues.into_iter()
+            .map(|queue| Self {
+                input_queue: Some(queue),
+                ..self.clone()
+            })
+            .collect())
+    }
+
+    /// the watcher reading a queue found by
+    /// discovery, with the rules of this one
+    pub fn discovered(&self, queue: &str) -> Self {
+        let mut watcher = Self {
+            input_queue: Some(queue.to_string()),
+            input_queue_pattern: None,
+            ..self.clone()
+        };
+        for rule in &mut watcher.rules {
+            rule.input_queue = Some(queue.to_string());
+        }
+        for (index, rule) in watcher.rules.iter_mut().enumerate() {
+            rule.debounce_scope = format!("{}/{}", queue, index);
+        }
+        watcher
+    }
+
+    /// check the input described by the configuration can be built,
+    /// or, for a watcher discovering its queues, the ones of
+    /// the watchers of the queues it will find
+    pub fn check_input(&self) -> Result<(), ConfError> {
+        match &self.input_queue_pattern {
+            Some(_) if self.input_queue.is_some() => Err(ConfError::InvalidWatcherInput),
+            Some(_) if self.taken_queue.is_some() => Err(ConfError::SharedTakenQueue),
+            Some(pattern) => self.discovered(pattern).input().map(|_| ()),
+            None => self.input().map(|_| ()),
+        }
+    }
+
+    /// the names under which the watcher can be paused and
+    /// resumed: its name, and its input queue, stream, or channels
+    pub fn control_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = [
+            &self.name,
+            &self.input_queue,
+            &self.input_queue_pattern,
+            &self.input_stream,
+            &self.input_channel,
+        ]
+        .iter()
+        .filter_map(|name| (*name).clone())
+        .collect();
+        names.extend(self.input_queues.iter().cloned());
+        names
+    }
+
+    /// the name of the lease of the watcher in the leader
+    /// election: its input queue, pattern, stream, channels,
+    /// or keyspace pattern
+    pub fn lease_name(&self) -> String {
+        let input = [
+            &self.input_queue,
+            &self.input_queue_pattern,
+            &self.input_stream,
+            &self.input_channel,
+        ]
+        .iter()
+        .find_map(|name| (*name).clone());
+        match (input, &self.input_keyspace) {
+            (Some(input), _) => input,
+            (None, Some(keyspace)) => format!("keyspace:{}", &keyspace.keys),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// a description of the input, for the logs and reports
+    pub fn label(&self) -> Result<String, ConfError> {
+        match &self.input_queue_pattern {
+            Some(pattern) => Ok(format!("queues matching {:?}", pattern)),
+            None => self.input().map(|input| input.to_string()),
+        }
+    }
+
+    /// build the input described by the configuration, which
+    /// must have exactly one of an input queue, an input stream,
+    /// an input channel, and an input keyspace
+    pub fn input(&self) -> Result<Input, ConfError> {
+        self.slot_input(0)
+    }
+
+    /// build the input of one of the `concurrency` slots of the
+    /// watcher. Each slot of a queue watcher has its own taken queue,
+    /// the first one being the configured one, and each slot of a
+    /// stream watcher is a distinct consumer.
+    pub fn slot_input(&self, slot: usize) -> Result<Input, ConfError> {
+        let inputs = [
+            self.input_queue.is_some(),
+            self.input_stream.is_some(),
+            self.input_channel.is_some(),
+            self.input_keyspace.is_some(),
+        ];
+        if inputs.iter().filter(|&&b| b).count() != 1 {
+            return Err(ConfError::InvalidWatcherInput);
+        }
+        if self.concurrency() > 1 && self.input_queue.is_none() && self.input_stream.is_none() {
+            return Err(ConfError::ConcurrentChannel);
+        }
+        match (&self.input_queue, &self.input_stream, &self.input_channel) {
+            (Some(queue), _, _) => {
+                let mut taken_queue = match &self.taken_queue {
+                    Some(taken_queue) => taken_queue.clone(),
+                    None => format!("{}/taken", queue),
+                };
+                if slot > 0 {
+                    taken_queue = format!("{}/{}", taken_queue, slot);
+                }
+                let tracked = self.stale_taken_age.is_some() || self.taken_timeout.is_some();
+                Ok(Input::Queue {
+                    queue: queue.clone(),
+                    taken_times: tracked.then(|| format!("{}/times", taken_queue)),
+                    taken_retries: self.taken_timeout.map(|_| format!("{}/retries", taken_queue)),
+                    taken_queue,
+                })
+            }
+            (_, Some(stream), _) => Ok(Input::Stream {
+                stream: stream.clone(),
+                group: self.consumer_group.clone().unwrap_or_else(|| "resc".to_string()),
+                consumer: {
+                    let consumer = self.consumer_name.as_deref().unwrap_or("resc");
+                    if slot > 0 {
+                        format!("{}-{}", consumer, slot)
+                    } else {
+                        consumer.to_string()
+                    }
+                },
+                field: self.stream_field.clone().unwrap_or_else(|| "task".to_string()),
+                reading_pending: false,
+            }),
+            (_, _, Some(pattern)) => Ok(Input::Channel {
+                pattern: pattern.clone(),
+                keyspace: None,
+                con: None,
+            }),
+            _ => Ok(Input::Channel {
+                pattern: String::new(), // computed on subscription
+                keyspace: self.input_keyspace.clone(),
+                con: None,
+            }),
+        }
+    }
+}
+
+/// A watcher watches the events incoming in one specific queue,
+/// stream, set of channels, or keyspace, and applies rules to generate tasks
 pub struct Watcher {
-    con: Connection,
+    redis_conf: RedisConf,
+    con: RedisConnection,
+    /// the other Redis servers this watcher writes to, by name
+    server_confs: HashMap<String, RedisConf>,
+    /// connections to the other Redis servers, by name
+    server_cons: HashMap<String, RedisConnection>,
     listener_channel: String,
-    input_queue: String,
-    taken_queue: String, // can't be shared between watchers
+    /// the sorted set where delayed tasks wait
+    delayed_set: String,
+    input: Input,
     ruleset: Ruleset,
+    unmatched_queue: Option<String>,
+    unmatched_counter_ttl: Option<usize>,
+    metrics: Option<Metrics>,
+    audit: Option<AuditConf>,
+    events: Option<LifecycleEvents>,
+    stale_taken_age: Option<f64>,
+    stale_taken_check_interval: Option<Duration>,
+    pop_timeout: usize,
+    /// the minimal delay between two events taken, if any
+    pop_interval: Option<Duration>,
+    /// whether the tasks are evaluated without anything being written
+    dry_run: bool,
+    /// whether the watcher is running and connected
+    up: Arc<AtomicBool>,
+    /// the names under which the watcher can be paused
+    control_names: Vec<String>,
+    paused: PausedWatchers,
+    /// the keys whose existence pauses the watcher
+    pause_keys: Vec<String>,
 }
 
 impl Watcher {
 
+    /// build the watcher of one of the `concurrency` slots
+    /// of a watcher configuration
     pub fn new(
         watcher_conf: &WatcherConf,
+        slot: usize,
         global_conf: &Conf,
+        paused: &PausedWatchers,
     ) -> Result<Self, RescError> {
         let listener_channel = global_conf.listener_channel.clone();
-        let input_queue = watcher_conf.input_queue.clone();
-        let taken_queue = match watcher_conf.taken_queue.as_ref() {
-            Some(queue) => queue.clone(),
-            None => format!("{}/taken", &input_queue),
-        };
-        let ruleset = Ruleset {
-            rules: watcher_conf.rules.clone(),
-        };
-        let client = redis::Client::open(&*global_conf.redis.url)?;
-        let con = client.get_connection()?;
+        let delayed_set = global_conf.delayed_set.clone();
+        let mut input = watcher_conf.slot_input(slot)?;
+        if global_conf.dry_run {
+            input = input.into_dry_run();
+        }
+        let mut rules = watcher_conf.rules.clone();
+        for rule in &mut rules {
+            rule.strict_patterns.get_or_insert(global_conf.strict_patterns);
+        }
+        let ruleset = Ruleset::new(rules, watcher_conf.match_mode);
+        let redis_conf = watcher_conf.redis.as_ref().unwrap_or(&global_conf.redis).clone();
+        let con = redis_conf.open_connection()?;
         debug!("got redis connection");
+        let mut server_confs = HashMap::new();
+        for rule in &watcher_conf.rules {
+            for maker in rule.makers.as_slice() {
+                if let Some(name) = &maker.redis {
+                    let server_conf = global_conf.redis_servers.get(name)
+                        .ok_or_else(|| ConfError::UnknownRedisServer(name.clone()))?;
+                    server_confs.insert(name.clone(), server_conf.clone());
+                }
+            }
+        }
+        let server_cons = open_connections(&server_confs)?;
+        let events = global_conf.events_channel.as_ref()
+            .filter(|_| !global_conf.dry_run)
+            .map(|channel| LifecycleEvents {
+                channel: channel.clone(),
+                watcher: input.to_string(),
+            });
+        let control_names = watcher_conf.control_names();
+        let pause_keys = global_conf.pause_key_prefix.as_ref()
+            .map(|prefix| control_names.iter().map(|name| format!("{}{}", prefix, name)).collect())
+            .unwrap_or_default();
         Ok(Self {
+            redis_conf,
             con,
+            server_confs,
+            server_cons,
             listener_channel,
-            input_queue,
-            taken_queue,
+            delayed_set,
+            input,
             ruleset,
+            unmatched_queue: watcher_conf.unmatched_queue.clone(),
+            unmatched_counter_ttl: watcher_conf.unmatched_counter_ttl,
+            metrics: global_conf.metrics.as_ref().map(Metrics::new).transpose()?,
+            audit: global_conf.audit.clone().filter(|_| !global_conf.dry_run),
+            events,
+            stale_taken_age: watcher_conf.stale_taken_age,
+            stale_taken_check_interval: watcher_conf.stale_taken_check_interval.map(Duration::from_secs),
+            pop_timeout: watcher_conf.pop_timeout.map_or(DEFAULT_POP_TIMEOUT, NonZeroUsize::get),
+            pop_interval: watcher_conf.slot_pop_interval(),
+            dry_run: global_conf.dry_run,
+            up: Arc::new(AtomicBool::new(false)),
+            control_names,
+            paused: paused.clone(),
+            pause_keys,
         })
     }
 
-    pub fn run(&mut self) -> Result<(), RescError> {
-        self.empty_taken_queue();
-        self.watch_input_queue()
+    /// a label of the watcher, and the flag telling whether
+    /// it's running and connected, for the health checks
+    pub fn health_state(&self) -> WatcherHealth {
+        (format!("watcher on {}", &self.input), Arc::clone(&self.up))
+    }
+
+    /// watch the input until `stop` is set
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), RescError> {
+        self.input.recover(&mut self.con, &self.redis_conf, self.stale_taken_age)?;
+        self.up.store(true, Ordering::Relaxed);
+        let res = self.watch_input(stop);
+        self.up.store(false, Ordering::Relaxed);
+        self.input.release(&mut self.con);
+        res
     }
 
-    /// move tasks from the taken queue to the input queue
+    /// replace the connections, until it works or the watcher is stopped,
+    /// then recover the input so that the event being handled
+    /// is handled again.
     ///
-    /// This is done on start to reschedule the tasks that
-    /// weren't completely handled.
-    fn empty_taken_queue(&mut self) {
-        debug!("watcher cleans its taken queue");
-        let mut n = 0;
-        while let Ok(taken) = self.con.rpoplpush::<_, String>(&self.taken_queue, &self.input_queue) {
-            debug!(
-                " moving {:?} from {:?} to {:?}",
-                &taken, &self.taken_queue, &self.input_queue
-            );
-            n += 1;
-        }
-        if n > 0 {
-            warn!(
-                "moved {} tasks from  {:?} to {:?}",
-                n, &self.taken_queue, &self.input_queue
-            );
-        }
-    }
-
-    /// completely handle one event received on the input queue
-    fn handle_input_event(&mut self, event: String) -> Result<(), RescError> {
+    /// When the server is managed by sentinels, the master is looked
+    /// for again, so that a failover is followed.
+    fn reconnect(&mut self, stop: &AtomicBool) {
+        self.up.store(false, Ordering::Relaxed);
+        let mut backoff = Backoff::new(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY);
+        for attempt in 1.. {
+            let delay = backoff.next_delay();
+            debug!("reconnection attempt {} in {:?}", attempt, delay);
+            sleep_unless_stopped(delay, stop);
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let cons = self.redis_conf.open_connection()
+                .and_then(|con| Ok((con, open_connections(&self.server_confs)?)));
+            match cons {
+                Ok((mut con, server_cons)) => {
+                    if let Err(e) = self.input.recover(&mut con, &self.redis_conf, None) {
+                        warn!("recovery of {} failed: {}", &self.input, e);
+                        continue;
+                    }
+                    info!("watcher on {} reconnected", &self.input);
+                    self.con = con;
+                    self.server_cons = server_cons;
+                    self.up.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) => {
+                    warn!("reconnection attempt {} failed: {}", attempt, e);
+                }
+            }
+        }
+    }
+
+    /// completely handle one event received on the input
+    fn handle_input_event(&mut self, input_event: InputEvent) -> Result<(), RescError> {
+        let mut latches = Vec::new();
+        let handled = self.apply_rules(input_event, &mut latches);
+        if handled.is_err() {
+            // so that the event isn't debounced when it's handled again
+            for latch in &latches {
+                release_latch(&mut self.con, latch);
+            }
+        }
+        handled
+    }
+
+    /// apply the rules to an event, and write the tasks they make,
+    /// collecting the debounce latches taken
+    fn apply_rules(
+        &mut self,
+        input_event: InputEvent,
+        latches: &mut Vec<String>,
+    ) -> Result<(), RescError> {
         let now = now_secs();
-        info!(
-            "<- got {:?} in queue {:?} @ {}",
-            &event, &self.input_queue, now
-        );
+        let event = &input_event.task;
+        info!("<- got {:?} in {} @ {}", event, &self.input, now);
+        let span = TraceSpan::root("task");
+        span.set_attribute("input", &self.input.to_string());
+        span.set_attribute("task", event);
+        self.publish_event(LifecycleEvent::TaskTaken { task: event })?;
 
         // we first compute all the rule results
         let mut results = Vec::new();
-        for rule in self.ruleset.matching_rules(&event) {
+        let matching_rules = {
+            let _match_span = span.child("match");
+            self.ruleset.matching_rules(event)
+        };
+        let matched = matching_rules.iter().any(|rule| !rule.dry_run);
+        let mut failure_policies = Vec::new();
+        for rule in matching_rules {
             debug!(" applying rule {:?}", rule.name);
-            match rule.results(&event) {
-                Ok(mut rule_results) => {
-                    results.append(&mut rule_results);
+            self.count("rule.matches", &[("rule", &rule.name)]);
+            let rule_span = span.child("rule");
+            rule_span.set_attribute("rule", &rule.name);
+            if let Some(events) = &self.events {
+                events.publish(&mut self.con, LifecycleEvent::RuleMatched {
+                    task: event,
+                    rule: &rule.name,
+                })?;
+            }
+            let mut context = FetchContext {
+                con: &mut self.con,
+                metrics: self.metrics.as_ref(),
+                span: &rule_span,
+                dry_run: self.dry_run,
+            };
+            match apply_rule(rule, event, &mut context) {
+                Ok((rule_results, _)) if rule.dry_run || self.dry_run => {
+                    for r in &rule_results {
+                        info!("  dry run of {:?}: would write {:?} to {}{}",
+                            rule.name,
+                            &r.task,
+                            &r.target,
+                            r.delay.map_or_else(String::new, |delay| format!(" in {}s", delay)),
+                        );
+                    }
+                }
+                Ok((rule_results, latch)) => {
+                    latches.extend(latch);
+                    results.extend(rule_results.into_iter().map(|r| (rule.name.clone(), r)));
+                }
+                Err(e) if rule.dry_run || self.dry_run => {
+                    warn!("  dry run of {:?} failed: {}", rule.name, e);
+                    rule_span.set_error(&e);
                 }
                 Err(e) => {
                     // A possible failure reason is a fetch not possible because of
                     // network or server condition.
-                    // TODO should we do something better ? Requeue ?
-                    error!("  Rule execution failed: {:?}", e);
+                    error!("  Rule {:?} failed ({}): {:?}", rule.name, &rule.on_failure, e);
+                    rule_span.set_error(&e);
+                    self.count("rule.failures", &[("rule", &rule.name)]);
+                    if let Some(events) = &self.events {
+                        if matches!(rule.on_failure, FailurePolicy::Drop | FailurePolicy::Retry(_)) {
+                            events.publish(&mut self.con, LifecycleEvent::TaskDropped {
+                                task: event,
+                                rule: Some(&rule.name),
+                                reason: "rule failed",
+                            })?;
+                        }
+                    }
+                    failure_policies.push(rule.on_failure.clone());
                 }
             }
         }
         debug!(" {} result(s)", results.len());
+        if !matched {
+            self.push_unmatched(event)?;
+        }
+
+        // we now apply the rule results, that is we push the tasks.
+        // The writes on the server of the watcher are done in the same
+        // transaction as the acknowledgment of the event, so that the event
+        // stays in the taken queue (or pending in the stream) unless all
+        // its tasks are written
+        let watcher_label = self.input.to_string();
+        let mut writes = redis::pipe();
+        self.apply_failure_policies(&input_event, &failure_policies, &mut writes)?;
+        // the produced tasks, with what became of them
+        let mut produced = Vec::new();
+        // the indexes of the produced tasks whose write depends on the
+        // transaction, with the index of the checking command
+        let mut checks = Vec::new();
+        for (rule_name, r) in results {
+            let write_span = span.child("write");
+            write_span.set_attribute("target", r.target.name());
+            if let Some(join) = &r.join {
+                let con = self.connection_for(r.redis.as_deref());
+                if !join.arrive(con)? {
+                    debug!("  {:?} waits for other parents in {:?}", &r.task, &join.key);
+                    self.count("tasks.joining", &[("target", r.target.name())]);
+                    continue;
+                }
+            }
+            match &r.redis {
+                Some(name) => {
+                    // another server, which can't be in the transaction
+                    let mut pipe = redis::pipe();
+                    let check = add_writes(&mut pipe, &r, &self.delayed_set, now);
+                    let con = self.connection_for(Some(name));
+                    let values = con.run_transaction(&pipe)?;
+                    let outcome = match check {
+                        Some(i) => WriteOutcome::from_script(&values[i])?,
+                        None => WriteOutcome::of_unchecked(&r),
+                    };
+                    produced.push((rule_name, r, outcome));
+                }
+                None => {
+                    if let Some(i) = add_writes(&mut writes, &r, &self.delayed_set, now) {
+                        checks.push((produced.len(), i));
+                    }
+                    let outcome = WriteOutcome::of_unchecked(&r);
+                    produced.push((rule_name, r, outcome));
+                }
+            }
+        }
+        self.input.ack(&mut writes, &input_event);
+        let values = self.con.run_transaction(&writes)?;
+        for (p, i) in checks {
+            produced[p].2 = WriteOutcome::from_script(&values[i])?;
+        }
 
-        // we now apply the rule results, that is we push the tasks
-        for r in results {
-            // if the rule specifies a task_set, we check the task isn't
-            // already present in the set
-            let in_set_time: Option<i32> = r.set.as_ref()
-                .and_then(|s| self.con.zscore(s, &r.task).ok());
-            if let Some(time) = in_set_time {
-                info!("  task {:?} already queued @ {}", &r.task, time);
+        for (rule_name, r, write) in produced {
+            let outcome = match write {
+                WriteOutcome::Duplicate => {
+                    info!("  task {:?} already queued", &r.task);
+                    AuditOutcome::AlreadyQueued
+                }
+                WriteOutcome::Pushed => {
+                    info!("  ->  {:?} pushed to {}", &r.task, &r.target);
+                    self.count("tasks.pushed", &[("target", r.target.name())]);
+                    AuditOutcome::Pushed
+                }
+                WriteOutcome::Delayed(delay) => {
+                    info!("  ->  {:?} delayed by {}s for {}", &r.task, delay, &r.target);
+                    self.count("tasks.delayed", &[("target", r.target.name())]);
+                    AuditOutcome::Delayed
+                }
+                WriteOutcome::RateLimited(delay) => {
+                    info!("  ->  {:?} deferred by {}s by the rate limit of {}", &r.task, delay, &r.target);
+                    self.count("tasks.rate_limited", &[("target", r.target.name())]);
+                    self.count("tasks.delayed", &[("target", r.target.name())]);
+                    AuditOutcome::Delayed
+                }
+                WriteOutcome::Parked => {
+                    // the mover will push it when the queue is short enough
+                    info!("  ->  {:?} parked, {} is full", &r.task, &r.target);
+                    self.count("tasks.parked", &[("target", r.target.name())]);
+                    self.count("tasks.delayed", &[("target", r.target.name())]);
+                    AuditOutcome::Delayed
+                }
+            };
+            self.audit(&AuditEntry {
+                time: now,
+                watcher: &watcher_label,
+                input: event,
+                rule: &rule_name,
+                task: &r.task,
+                target: &r.target,
+                outcome,
+            })?;
+            if write == WriteOutcome::Duplicate {
                 continue;
             }
-            info!("  ->  {:?} pushed to queue {:?}", &r.task, &r.queue);
-            if let Some(task_set) = r.set.as_ref() {
-                // we push first to the task set, to avoid a race condition:
-                // a worker not finding the task in the set
-                self.con.zadd(task_set, &r.task, now)?;
-                debug!(
-                    "      {:?} pushed to task_set {:?} @ {}",
-                    &r.task, task_set, now
-                );
-            }
-            self.con.lpush(&r.queue, &r.task)?;
-            self.con.publish(
+            self.publish_event(LifecycleEvent::TaskCreated {
+                task: event,
+                rule: &rule_name,
+                created: &r.task,
+                target: r.target.name(),
+                delay: write.delay(),
+            })?;
+            let _: () = self.con.publish(
                 &self.listener_channel,
-                format!("{} TRIGGER {} -> {}", &self.taken_queue, &event, &r.task),
+                format!("{} TRIGGER {} -> {}", self.input.label(), event, &r.task),
             )?;
         }
+        if !self.dry_run {
+            let _: () = self.con.publish(
+                &self.listener_channel,
+                format!("{} DONE {}", self.input.label(), event),
+            )?;
+        }
+        debug!(" done with task {:?}", event);
+        Ok(())
+    }
+
+    /// handle the task according to the policies of the rules
+    /// which failed. The task is requeued or sent to a given
+    /// dead letter queue only once, even when several rules failed.
+    ///
+    /// The pushes to the dead letter queues are added to the pipeline
+    /// acknowledging the event, so that they're done only once.
+    fn apply_failure_policies(
+        &mut self,
+        input_event: &InputEvent,
+        policies: &[FailurePolicy],
+        writes: &mut redis::Pipeline,
+    ) -> Result<(), RescError> {
+        let event = &input_event.task;
+        if policies.contains(&FailurePolicy::Requeue) {
+            if self.input.requeue(&mut self.con, input_event)? {
+                info!("  {:?} requeued in {}", event, &self.input);
+            } else {
+                warn!("  {:?} can't be requeued in {}", event, &self.input);
+            }
+        }
+        let mut dead_letter_queues: Vec<&String> = policies.iter()
+            .filter_map(|policy| match policy {
+                FailurePolicy::DeadLetter(queue) => Some(queue),
+                _ => None,
+            })
+            .collect();
+        dead_letter_queues.sort();
+        dead_letter_queues.dedup();
+        for queue in dead_letter_queues {
+            info!("  {:?} pushed to dead letter queue {:?}", event, queue);
+            writes.lpush(queue, event).ignore();
+        }
+        Ok(())
+    }
+
+    /// the connection to the named Redis server,
+    /// or to the one of the watcher
+    fn connection_for(&mut self, redis: Option<&str>) -> &mut RedisConnection {
+        match redis {
+            Some(name) => self.server_cons.get_mut(name).unwrap(), // checked in new
+            None => &mut self.con,
+        }
+    }
+
+    /// publish a lifecycle event, if an events channel is configured
+    fn publish_event(&mut self, event: LifecycleEvent<'_>) -> Result<(), RescError> {
+        if let Some(events) = &self.events {
+            events.publish(&mut self.con, event)?;
+        }
+        Ok(())
+    }
+
+    /// record a produced task, if an audit trail is configured
+    fn audit(&mut self, entry: &AuditEntry<'_>) -> Result<(), RescError> {
+        if let Some(audit) = &self.audit {
+            audit.record(&mut self.con, entry)?;
+        }
+        Ok(())
+    }
+
+    /// increment a counter, if metrics are configured
+    fn count(&self, name: &str, tags: &[(&str, &str)]) {
+        if let Some(metrics) = &self.metrics {
+            metrics.count(name, 1, tags);
+        }
+    }
 
-        // the event can now be removed from the taken queue
-        self.con.lrem(&self.taken_queue, 1, &event)?;
-        self.con.publish(
-            &self.listener_channel,
-            format!("{} DONE {}", &self.taken_queue, &event),
-        )?;
-        debug!(" done with task {:?}", &event);
+    /// keep aside a task matched by no rule, if an
+    /// unmatched queue is configured
+    fn push_unmatched(&mut self, event: &str) -> Result<(), RescError> {
+        self.count("tasks.unmatched", &[]);
+        let queue = match &self.unmatched_queue {
+            Some(queue) => queue,
+            None => {
+                debug!(" no matching rule");
+                return self.publish_event(LifecycleEvent::TaskDropped {
+                    task: event,
+                    rule: None,
+                    reason: "no matching rule",
+                });
+            }
+        };
+        if self.dry_run {
+            info!("  dry run: no matching rule, {:?} would be pushed to queue {:?}", event, queue);
+            return Ok(());
+        }
+        info!("  no matching rule, {:?} pushed to queue {:?}", event, queue);
+        let _: () = self.con.lpush(queue, event)?;
+        if let Some(ttl) = self.unmatched_counter_ttl {
+            let counter = format!("{}/count", queue);
+            let _: () = self.con.incr(&counter, 1)?;
+            let _: () = self.con.expire(&counter, ttl as i64)?;
+        }
         Ok(())
     }
 
-    /// continuously watch the input queue an apply rules on the events
-    /// it takes in the queue
-    fn watch_input_queue(&mut self) -> Result<(), RescError> {
-        info!("watcher launched on queue {:?}...", &self.input_queue);
-        loop {
-            match self.con.brpoplpush(&self.input_queue, &self.taken_queue, 0) {
-                Ok(event) => {
-                    self.handle_input_event(event)?
+    /// whether the watcher is paused, by the control
+    /// channel or by one of its pause keys
+    fn is_paused(&mut self) -> Result<bool, RescError> {
+        if self.paused.is_paused(&self.control_names) {
+            return Ok(true);
+        }
+        if self.pause_keys.is_empty() {
+            return Ok(false);
+        }
+        let existing: usize = self.con.exists(&self.pause_keys)?;
+        Ok(existing > 0)
+    }
+
+    /// continuously watch the input an apply rules on the events
+    /// it takes, until `stop` is set.
+    ///
+    /// The event being handled when `stop` is set is completely handled
+    /// before the function returns.
+    fn watch_input(&mut self, stop: &AtomicBool) -> Result<(), RescError> {
+        info!("watcher launched on {}...", &self.input);
+        let mut last_stale_check = Instant::now();
+        let mut next_pop = Instant::now();
+        let mut was_paused = false;
+        while !stop.load(Ordering::Relaxed) {
+            let paused = match self.is_paused() {
+                Ok(paused) => paused,
+                Err(e) => {
+                    error!("checking the pause keys of {} failed : {}", &self.input, e);
+                    self.reconnect(stop);
+                    continue;
+                }
+            };
+            if paused != was_paused {
+                was_paused = paused;
+                info!("watcher on {} {}", &self.input, if paused { "paused" } else { "resumed" });
+            }
+            if paused {
+                sleep_unless_stopped(PAUSE_CHECK_PERIOD, stop);
+                continue;
+            }
+            if self.pop_interval.is_some() {
+                let now = Instant::now();
+                if now < next_pop {
+                    sleep_unless_stopped(next_pop - now, stop);
+                    continue;
                 }
+            }
+            if let (Some(age), Some(interval)) = (self.stale_taken_age, self.stale_taken_check_interval) {
+                if last_stale_check.elapsed() >= interval {
+                    last_stale_check = Instant::now();
+                    if let Err(e) = self.input.requeue_stale(&mut self.con, age, false) {
+                        warn!("looking for stale tasks in {} failed: {}", &self.input, e);
+                    }
+                }
+            }
+            match self.input.next_event(&mut self.con, self.pop_timeout) {
+                Ok(Some(event)) => {
+                    if let Some(interval) = self.pop_interval {
+                        next_pop = Instant::now() + interval;
+                    }
+                    if let Err(e) = self.handle_input_event(event) {
+                        // the event is still in the taken queue, or pending in
+                        // the stream, and will be handled again after reconnection
+                        error!("handling of event failed : {}", e);
+                        self.reconnect(stop);
+                    }
+                }
+                Ok(None) => {} // timeout
                 Err(e) => {
-                    error!("BRPOPLPUSH on {:?} failed : {}", &self.input_queue, e);
+                    error!("reading {} failed : {}", &self.input, e);
+                    self.reconnect(stop);
+                }
+            }
+        }
+        info!("watcher on {} stopped", &self.input);
+        Ok(())
+    }
+
+}
+
+/// what became of a task in the transaction writing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WriteOutcome {
+    /// not written, being a duplicate
+    Duplicate,
+    /// written to its queue, stream, or sorted set
+    Pushed,
+    /// written to the delayed set, due in the given number of seconds
+    Delayed(f64),
+    /// written to the delayed set because its queue was full
+    Parked,
+    /// written to the delayed set, due in the given number of
+    /// seconds, because of the rate limit
+    RateLimited(f64),
+}
+
+impl WriteOutcome {
+    /// the outcome of a task written without the script,
+    /// which can't fail to be written
+    fn of_unchecked(r: &RuleResult) -> Self {
+        r.delay.map_or(Self::Pushed, Self::Delayed)
+    }
+    /// read the value returned by the write script
+    fn from_script(value: &redis::Value) -> Result<Self, RescError> {
+        let (code, delay): (i64, f64) = redis::from_redis_value(value)?;
+        Ok(match code {
+            0 => Self::Duplicate,
+            2 => Self::Delayed(delay),
+            3 => Self::Parked,
+            4 => Self::RateLimited(delay),
+            _ => Self::Pushed,
+        })
+    }
+    /// the delay of the task, when it's not directly pushed
+    fn delay(self) -> Option<f64> {
+        match self {
+            Self::Delayed(delay) | Self::RateLimited(delay) => Some(delay),
+            Self::Parked => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
+/// add to a pipeline the commands writing the task of a rule result
+/// to its queue or stream, with its side effects, or to the delayed
+/// set when the task is delayed.
+///
+/// When the write depends on the state of the server, because the task
+/// is deduplicated, rate limited, or its queue has a maximal length, it's
+/// done by a script which checks it atomically. The index of this command in the
+/// pipeline is then returned.
+fn add_writes(
+    pipe: &mut redis::Pipeline,
+    r: &RuleResult,
+    delayed_set: &str,
+    now: f64,
+) -> Option<usize> {
+    add_join_completion(pipe, r);
+    // a delayed task is parked, if needed, by the mover when it's due
+    let max_queue_len = match (&r.target, r.delay) {
+        (TaskTarget::Queue(_), None) => r.max_queue_len,
+        _ => None,
+    };
+    if r.dedup.is_none() && max_queue_len.is_none() && r.rate_limit.is_none() {
+        if let Some(delay) = r.delay {
+            // the mover will write the task when it's due
+            let delayed = DelayedTask::new(r);
+            pipe.zadd(delayed_set, delayed.to_member(), now + delay);
+        } else {
+            match &r.target {
+                TaskTarget::Queue(queue) => {
+                    pipe.cmd(r.push.command()).arg(queue).arg(&r.task);
+                }
+                TaskTarget::Stream { stream, fields } => {
+                    pipe.xadd(stream, "*", fields);
+                }
+                TaskTarget::Zset { zset, score } => {
+                    pipe.zadd(zset, &r.task, *score);
                 }
             }
+            for effect in &r.effects {
+                effect.add_to(pipe);
+            }
+        }
+        return None;
+    }
+    let index = pipe.cmd_iter().count();
+    let (dedup_key, dedup_kind, ttl) = match &r.dedup {
+        Some(Dedup::Set { set, ttl }) => (Some(set), "set", *ttl),
+        Some(Dedup::Key { key, ttl }) => (Some(key), "key", *ttl),
+        None => (None, "", None),
+    };
+    let bucket = r.rate_limit.map(|_| RateLimit::bucket(&r.target));
+    pipe.cmd("EVAL")
+        .arg(WRITE_SCRIPT.as_str())
+        .arg(4)
+        .arg(r.target.name())
+        .arg(delayed_set)
+        .arg(dedup_key.map_or(r.target.name(), String::as_str))
+        .arg(bucket.as_deref().unwrap_or(r.target.name()))
+        .arg(&r.task)
+        .arg(now)
+        .arg(r.delay.map_or_else(String::new, |delay| (now + delay).to_string()))
+        .arg(DelayedTask::new(r).to_member())
+        .arg(dedup_kind)
+        .arg(ttl.unwrap_or(0))
+        .arg(max_queue_len.map_or_else(String::new, |len| len.to_string()))
+        .arg(r.rate_limit.map_or_else(String::new, |limit| limit.interval().to_string()))
+        .arg(r.rate_limit.map_or(0.0, |limit| limit.period))
+        .arg(write_args(&r.target, r.push, &r.effects));
+    Some(index)
+}
+
+/// add to the pipeline the removal of the set of the
+/// parents of the completed join of the task, if any
+fn add_join_completion(pipe: &mut redis::Pipeline, r: &RuleResult) {
+    if let Some(join) = &r.join {
+        pipe.del(&join.key);
+    }
+}
+
+/// compute the results of a rule, trying again with
+/// a backoff when the rule's policy says so
+fn apply_rule(
+    rule: &Rule,
+    event: &str,
+    context: &mut FetchContext<'_>,
+) -> Result<(Vec<RuleResult>, Option<String>), RescError> {
+    let retries = match rule.on_failure {
+        FailurePolicy::Retry(n) => n,
+        _ => 0,
+    };
+    let mut backoff = Backoff::new(RETRY_MIN_DELAY, RETRY_MAX_DELAY);
+    let mut attempt = 0;
+    loop {
+        match rule.results(event, context) {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                let delay = backoff.next_delay();
+                warn!("  Rule {:?} failed: {}, retry {}/{} in {:?}", rule.name, e, attempt, retries, delay);
+                thread::sleep(delay);
+            }
+            res => {
+                return res;
+            }
         }
     }
+}
 
+fn open_connections(
+    confs: &HashMap<String, RedisConf>,
+) -> Result<HashMap<String, RedisConnection>, RescError> {
+    let mut cons = HashMap::new();
+    for (name, conf) in confs {
+        cons.insert(name.clone(), conf.open_connection()?);
+        debug!("got connection to redis server {:?}", name);
+    }
+    Ok(cons)
 }
 
 /// build the Epoch related timestamp, in seconds as f64
 /// because we want to use in in JSON and JS. Precision
 /// in f64 is not lost because this number is smaller than 2^51.
-fn now_secs() -> f64 {
+pub fn now_secs() -> f64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()